@@ -0,0 +1,253 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::common;
+use crate::config::Config;
+use crate::context;
+use crate::error::Error;
+use crate::result::{Argon2Result, JsResult, Result};
+use crate::trace::{Trace, TraceEvent};
+use serde::{Deserialize, Serialize};
+
+use wasm_bindgen::prelude::*;
+
+/// Statistics of the reference selections of a run. Matrices are stored row
+/// by row so they can be rendered as heatmaps.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ReferenceStats {
+    /// The number of lanes.
+    pub lanes: u32,
+
+    /// The number of blocks in a lane.
+    pub lane_length: u32,
+
+    /// The number of reference selections.
+    pub references: u64,
+
+    /// How many times every block is referenced, one row per lane.
+    pub counts: Vec<u32>,
+
+    /// The number of references into every lane.
+    pub per_lane: Vec<u64>,
+
+    /// The number of references into every slice.
+    pub per_slice: Vec<u64>,
+
+    /// The number of references from the lane of the row into the lane of
+    /// the column.
+    pub lane_matrix: Vec<u64>,
+
+    /// The number of references from the slice of the row into the slice of
+    /// the column.
+    pub slice_matrix: Vec<u64>,
+
+    /// The number of references into the lane of the block being filled.
+    pub same_lane: u64,
+
+    /// The number of references into another lane.
+    pub cross_lane: u64,
+
+    /// The fraction of references into another lane.
+    pub cross_lane_ratio: f64,
+
+    /// The number of references for every distance, the number of blocks
+    /// from the reference block forward to the block being filled within a
+    /// lane.
+    pub distances: Vec<u64>,
+}
+
+impl ReferenceStats {
+    /// Computes the statistics of the reference selections in a trace of a
+    /// run with the supplied number of lanes and lane length. Fails with
+    /// `Error::IncorrectArgument` when a selection lies outside that memory.
+    pub fn new(trace: &Trace, lanes: u32, lane_length: u32) -> Result<ReferenceStats> {
+        if lanes == 0 || lane_length == 0 || lane_length % common::SYNC_POINTS != 0 {
+            return Err(Error::IncorrectArgument);
+        }
+        let slices = common::SYNC_POINTS as usize;
+        let segment_length = lane_length / common::SYNC_POINTS;
+        let (lanes_len, lane_len) = (lanes as usize, lane_length as usize);
+        let mut stats = ReferenceStats {
+            lanes,
+            lane_length,
+            references: 0,
+            counts: vec![0; lanes_len * lane_len],
+            per_lane: vec![0; lanes_len],
+            per_slice: vec![0; slices],
+            lane_matrix: vec![0; lanes_len * lanes_len],
+            slice_matrix: vec![0; slices * slices],
+            same_lane: 0,
+            cross_lane: 0,
+            cross_lane_ratio: 0.0,
+            distances: vec![0; lane_len],
+        };
+        for event in trace.events.iter() {
+            let selection = match event {
+                TraceEvent::ReferenceSelection(selection) => selection,
+                _ => continue,
+            };
+            if selection.lane >= lanes
+                || selection.ref_lane >= lanes
+                || selection.index >= lane_length
+                || selection.ref_index >= lane_length
+                || selection.slice >= common::SYNC_POINTS
+            {
+                return Err(Error::IncorrectArgument);
+            }
+            let (lane, ref_lane) = (selection.lane as usize, selection.ref_lane as usize);
+            let ref_slice = (selection.ref_index / segment_length) as usize;
+            stats.references += 1;
+            stats.counts[ref_lane * lane_len + selection.ref_index as usize] += 1;
+            stats.per_lane[ref_lane] += 1;
+            stats.per_slice[ref_slice] += 1;
+            stats.lane_matrix[lane * lanes_len + ref_lane] += 1;
+            stats.slice_matrix[selection.slice as usize * slices + ref_slice] += 1;
+            if lane == ref_lane {
+                stats.same_lane += 1;
+            } else {
+                stats.cross_lane += 1;
+            }
+            let distance = (selection.index as u64 + lane_length as u64
+                - selection.ref_index as u64)
+                % lane_length as u64;
+            stats.distances[distance as usize] += 1;
+        }
+        if stats.references > 0 {
+            stats.cross_lane_ratio = stats.cross_lane as f64 / stats.references as f64;
+        }
+        Ok(stats)
+    }
+
+    /// Computes the statistics of the reference selections in the trace of a
+    /// result hashed with the supplied configuration.
+    pub fn from_result(result: &Argon2Result, config: &Config) -> Result<ReferenceStats> {
+        context::check_layout(config)?;
+        let lane_length = context::segment_length(config) * common::SYNC_POINTS;
+        ReferenceStats::new(&result.trace, config.lanes, lane_length)
+    }
+}
+
+/// Computes the reference statistics of a result returned by
+/// `hash_encoded_js` with the same configuration, as JSON.
+#[wasm_bindgen]
+pub fn reference_stats_js(result_json: String, config_json: String) -> JsResult<String> {
+    let result = Argon2Result::from_json(&result_json)?;
+    let config = Config::from_json(&config_json)?;
+    let stats = ReferenceStats::from_result(&result, &config)?;
+    Ok(serde_json::to_string(&stats).map_err(Error::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::analysis::{reference_stats_js, ReferenceStats};
+    use crate::argon2::hash_encoded;
+    use crate::config::Config;
+    use crate::error::Error;
+    use crate::result::Argon2Result;
+    use crate::trace::{TraceLevel, TraceOptions};
+    use crate::variant::Variant;
+
+    fn stats(variant: Variant) -> ReferenceStats {
+        let config = Config {
+            variant,
+            lanes: 4,
+            mem_cost: 256,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::References),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+        ReferenceStats::from_result(&result, &config).unwrap()
+    }
+
+    #[test]
+    fn new_counts_every_reference() {
+        let stats = stats(Variant::Argon2d);
+        let references = 2 * 256 - 2 * 4;
+        assert_eq!(stats.references, references);
+        assert_eq!(stats.counts.len(), 256);
+        assert_eq!(
+            stats.counts.iter().map(|&c| c as u64).sum::<u64>(),
+            references
+        );
+        assert_eq!(stats.per_lane.iter().sum::<u64>(), references);
+        assert_eq!(stats.per_slice.iter().sum::<u64>(), references);
+        assert_eq!(stats.lane_matrix.iter().sum::<u64>(), references);
+        assert_eq!(stats.slice_matrix.iter().sum::<u64>(), references);
+        assert_eq!(stats.distances.iter().sum::<u64>(), references);
+        assert_eq!(stats.same_lane + stats.cross_lane, references);
+        assert_eq!(
+            stats.cross_lane_ratio,
+            stats.cross_lane as f64 / references as f64
+        );
+    }
+
+    #[test]
+    fn new_never_references_the_block_itself() {
+        let stats = stats(Variant::Argon2i);
+        assert_eq!(stats.distances[0], 0);
+        assert!(stats.cross_lane > 0);
+    }
+
+    #[test]
+    fn from_result_rejects_a_mismatched_config() {
+        let config = Config {
+            lanes: 2,
+            mem_cost: 32,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+
+        let fewer_lanes = Config {
+            lanes: 1,
+            ..config.clone()
+        };
+        assert_eq!(
+            ReferenceStats::from_result(&result, &fewer_lanes),
+            Err(Error::IncorrectArgument)
+        );
+        let no_lanes = Config::from_json(r#"{"memory": "32", "parallelism": "0"}"#).unwrap();
+        assert_eq!(
+            ReferenceStats::from_result(&result, &no_lanes),
+            Err(Error::LanesTooFew)
+        );
+        assert_eq!(
+            ReferenceStats::new(&result.trace, 2, 6),
+            Err(Error::IncorrectArgument)
+        );
+    }
+
+    #[test]
+    fn data_dependent_references_differ_from_independent_ones() {
+        assert_ne!(stats(Variant::Argon2d), stats(Variant::Argon2i));
+    }
+
+    #[test]
+    fn reference_stats_js_returns_json() {
+        let config_json = r#"{"memory": "32", "parallelism": "2", "iterations": "1",
+            "trace_level": "references"}"#;
+        let mut result = Argon2Result::new();
+        hash_encoded(
+            b"password",
+            b"somesalt",
+            &Config::from_json(config_json).unwrap(),
+            &mut result,
+        )
+        .unwrap();
+        let json = reference_stats_js(result.to_json(), config_json.to_string()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["lane_length"], 16);
+        assert_eq!(value["references"], 32 - 4);
+        assert_eq!(value["lane_matrix"].as_array().unwrap().len(), 4);
+    }
+}
@@ -6,14 +6,20 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use crate::bytes::{self, ByteEncoding};
 use crate::config::Config;
 use crate::context::Context;
 use crate::core;
 use crate::encoding;
+use crate::error::Error;
 use crate::memory::Memory;
+use crate::progress::{CancellationToken, Hooks, Progress};
 use crate::result::Argon2Result;
-use crate::result::Result;
+use crate::result::{JsResult, Result};
+use crate::sink::{CallbackSink, NoopSink, TraceSink};
+use crate::snapshot::SnapshotMode;
 use crate::thread_mode::ThreadMode;
+use crate::trace::{Trace, TraceOptions};
 use crate::variant::Variant;
 use crate::version::Version;
 
@@ -100,30 +106,188 @@ pub fn encoded_len(
 /// let encoded = argon2::hash_encoded(pwd, salt, &config).unwrap();
 /// ```
 ///
-pub fn hash_encoded(pwd: &[u8], salt: &[u8], config: &Config, state: &mut Argon2Result) -> String {
-    let context = Context::new(config.clone(), pwd, salt).unwrap();
+/// When `config.stop_at_iteration` is set, hashing stops after that pass, the
+/// result is marked as partial and holds the memory state, and `None` is
+/// returned instead of the encoded hash.
+pub fn hash_encoded(
+    pwd: &[u8],
+    salt: &[u8],
+    config: &Config,
+    state: &mut Argon2Result,
+) -> Result<Option<String>> {
+    let mut trace = Trace::new();
+    let encoded = hash_encoded_with_sink(pwd, salt, config, state, &mut trace);
+    state.trace.append(&mut trace);
+    encoded
+}
+
+/// Hashes the password like `hash_encoded`, but hands the trace events to
+/// the sink in batches while hashing instead of keeping them in the result.
+pub fn hash_encoded_with_sink(
+    pwd: &[u8],
+    salt: &[u8],
+    config: &Config,
+    state: &mut Argon2Result,
+    sink: &mut dyn TraceSink,
+) -> Result<Option<String>> {
+    hash_encoded_with_hooks(pwd, salt, config, state, &mut Hooks::new(sink))
+}
+
+/// Hashes the password like `hash_encoded_with_sink`, additionally reporting
+/// the progress to the hooks and stopping with `Error::Cancelled` when their
+/// cancellation token is set.
+pub fn hash_encoded_with_hooks(
+    pwd: &[u8],
+    salt: &[u8],
+    config: &Config,
+    state: &mut Argon2Result,
+    hooks: &mut Hooks,
+) -> Result<Option<String>> {
+    let context = Context::new(config.clone(), pwd, salt)?;
 
-    let hash = run(&context, state);
-    let encoded = encoding::encode_string(&context, &hash);
-    state.set_hash(&encoded);
-    return encoded;
+    let encoded = run(&context, state, hooks)?.map(|hash| {
+        let encoded = encoding::encode_string(&context, &hash);
+        state.set_hash(&encoded);
+        encoded
+    });
+    Ok(encoded)
 }
 
 #[wasm_bindgen]
-pub fn hash_encoded_js(pwd: String, salt: String, config_json: String) -> String {
+pub fn hash_encoded_js(pwd: String, salt: String, config_json: String) -> JsResult<String> {
     panic::set_hook(Box::new(console_error_panic_hook::hook));
-    let config = Config::from_json(config_json.as_str());
+    let config = Config::from_json(config_json.as_str())?;
     web_sys::console::log_1(&JsValue::from_str(
         format!(
             "Running Argon2 with config: {}",
-            serde_json::to_string(&config).unwrap()
+            serde_json::to_string(&config).map_err(Error::from)?
         )
         .as_str(),
     ));
-    let mut result = Argon2Result::new();
+    let mut result = Argon2Result::with_encoding(config.trace_encoding);
+
+    hash_encoded(pwd.as_bytes(), salt.as_bytes(), &config, &mut result)?;
+    Ok(result.to_json())
+}
+
+/// Hashes the password like `hash_encoded_js`, taking the password and salt
+/// as byte arrays such as a `Uint8Array`. The optional `secret` and `ad`
+/// are the secret value and the associated data.
+#[wasm_bindgen]
+pub fn hash_encoded_bytes_js(
+    pwd: &[u8],
+    salt: &[u8],
+    config_json: String,
+    secret: Option<Vec<u8>>,
+    ad: Option<Vec<u8>>,
+) -> JsResult<String> {
+    panic::set_hook(Box::new(console_error_panic_hook::hook));
+    let secret = secret.unwrap_or_default();
+    let ad = ad.unwrap_or_default();
+    let config = Config {
+        secret: &secret,
+        ad: &ad,
+        ..Config::from_json(config_json.as_str())?
+    };
+    let mut result = Argon2Result::with_encoding(config.trace_encoding);
+
+    hash_encoded(pwd, salt, &config, &mut result)?;
+    Ok(result.to_json())
+}
+
+/// Hashes the password like `hash_encoded_js`, calling `on_events` with a
+/// JSON array of trace events for every batch while hashing. The returned
+/// result holds no events. When `on_events` throws the run is stopped and
+/// its exception is thrown instead.
+#[wasm_bindgen]
+pub fn hash_encoded_stream_js(
+    pwd: String,
+    salt: String,
+    config_json: String,
+    on_events: &js_sys::Function,
+) -> JsResult<String> {
+    panic::set_hook(Box::new(console_error_panic_hook::hook));
+    let config = Config::from_json(config_json.as_str())?;
+    let mut result = Argon2Result::with_encoding(config.trace_encoding);
+    let encoding = config.trace_encoding;
+    let stop = CancellationToken::new();
+    let mut exception = None;
+    let mut sink = CallbackSink::new(|events| {
+        if exception.is_none() {
+            let json = bytes::to_json(&events, encoding);
+            exception = on_events
+                .call1(&JsValue::NULL, &JsValue::from_str(&json))
+                .err();
+            if exception.is_some() {
+                stop.cancel();
+            }
+        }
+    });
+    let mut hooks = Hooks {
+        cancel: Some(&stop),
+        ..Hooks::new(&mut sink)
+    };
 
-    hash_encoded(pwd.as_bytes(), salt.as_bytes(), &config, &mut result);
-    result.to_json()
+    let hashed = hash_encoded_with_hooks(
+        pwd.as_bytes(),
+        salt.as_bytes(),
+        &config,
+        &mut result,
+        &mut hooks,
+    );
+    if let Some(exception) = exception {
+        return Err(exception);
+    }
+    hashed?;
+    Ok(result.to_json())
+}
+
+/// Hashes the password like `hash_encoded_js`, calling `on_progress` with
+/// the number of blocks filled and the total number of blocks after every
+/// segment. Fails with `Error::Cancelled` when `token` is cancelled, for
+/// example from within `on_progress`. When `on_progress` throws the run is
+/// stopped, leaving `token` untouched, and its exception is thrown instead.
+#[wasm_bindgen]
+pub fn hash_encoded_progress_js(
+    pwd: String,
+    salt: String,
+    config_json: String,
+    on_progress: &js_sys::Function,
+    token: &CancellationToken,
+) -> JsResult<String> {
+    panic::set_hook(Box::new(console_error_panic_hook::hook));
+    let config = Config::from_json(config_json.as_str())?;
+    let mut result = Argon2Result::with_encoding(config.trace_encoding);
+    let mut trace = Trace::new();
+    let stop = token.child();
+    let mut exception = None;
+    let mut callback = |progress: Progress| {
+        let done = JsValue::from_f64(progress.blocks_done as f64);
+        let total = JsValue::from_f64(progress.blocks_total as f64);
+        if let Err(e) = on_progress.call2(&JsValue::NULL, &done, &total) {
+            exception = Some(e);
+            stop.cancel();
+        }
+    };
+    let mut hooks = Hooks {
+        progress: Some(&mut callback),
+        cancel: Some(&stop),
+        ..Hooks::new(&mut trace)
+    };
+
+    let hashed = hash_encoded_with_hooks(
+        pwd.as_bytes(),
+        salt.as_bytes(),
+        &config,
+        &mut result,
+        &mut hooks,
+    );
+    if let Some(exception) = exception {
+        return Err(exception);
+    }
+    hashed?;
+    result.trace = trace;
+    Ok(result.to_json())
 }
 
 #[wasm_bindgen]
@@ -169,8 +333,20 @@ pub fn create_default_config() -> String {
 /// let vec = argon2::hash_raw(pwd, salt, &config).unwrap();
 /// ```
 pub fn hash_raw(pwd: &[u8], salt: &[u8], config: &Config) -> Result<Vec<u8>> {
-    let context = Context::new(config.clone(), pwd, salt)?;
-    let hash = run(&context, &mut Argon2Result::new());
+    let config = Config {
+        stop_at_iteration: None,
+        trace: TraceOptions::off(),
+        snapshot: SnapshotMode::Off,
+        ..config.clone()
+    };
+    let context = Context::new(config, pwd, salt)?;
+    let mut sink = NoopSink;
+    let hash = run(
+        &context,
+        &mut Argon2Result::new(),
+        &mut Hooks::new(&mut sink),
+    )?
+    .unwrap();
     Ok(hash)
 }
 
@@ -192,9 +368,32 @@ pub fn verify_encoded(encoded: &str, pwd: &[u8]) -> Result<bool> {
 }
 
 #[wasm_bindgen]
-pub fn verify_encoded_js(encoded: String, pwd: String) -> bool {
-    verify_encoded_ext(encoded.as_str(), pwd.as_bytes(), &[], &[]).unwrap()
+pub fn verify_encoded_js(encoded: String, pwd: String) -> JsResult<bool> {
+    Ok(verify_encoded_ext(
+        encoded.as_str(),
+        pwd.as_bytes(),
+        &[],
+        &[],
+    )?)
+}
+
+/// Verifies the password, given as a byte array such as a `Uint8Array`, with
+/// the encoded hash and the optional secret value and associated data.
+#[wasm_bindgen]
+pub fn verify_encoded_bytes_js(
+    encoded: String,
+    pwd: &[u8],
+    secret: Option<Vec<u8>>,
+    ad: Option<Vec<u8>>,
+) -> JsResult<bool> {
+    Ok(verify_encoded_ext(
+        encoded.as_str(),
+        pwd,
+        &secret.unwrap_or_default(),
+        &ad.unwrap_or_default(),
+    )?)
 }
+
 /// Verifies the password with the encoded hash, secret and associated data.
 ///
 /// # Examples
@@ -227,7 +426,10 @@ pub fn verify_encoded_ext(encoded: &str, pwd: &[u8], secret: &[u8], ad: &[u8]) -
         secret,
         ad,
         hash_length: decoded.hash.len() as u32,
-        stop_at_iteration: 0,
+        stop_at_iteration: None,
+        trace_encoding: ByteEncoding::default(),
+        trace: TraceOptions::off(),
+        snapshot: SnapshotMode::Off,
     };
     verify_raw(pwd, &decoded.salt, &decoded.hash, &config)
 }
@@ -252,18 +454,30 @@ pub fn verify_encoded_ext(encoded: &str, pwd: &[u8], secret: &[u8], ad: &[u8]) -
 pub fn verify_raw(pwd: &[u8], salt: &[u8], hash: &[u8], config: &Config) -> Result<bool> {
     let config = Config {
         hash_length: hash.len() as u32,
+        stop_at_iteration: None,
+        trace: TraceOptions::off(),
+        snapshot: SnapshotMode::Off,
         ..config.clone()
     };
     let context = Context::new(config, pwd, salt)?;
-    let calculated_hash = run(&context, &mut Argon2Result::new());
+    let mut sink = NoopSink;
+    let mut hooks = Hooks::new(&mut sink);
+    let calculated_hash = run(&context, &mut Argon2Result::new(), &mut hooks)?.unwrap();
     Ok(constant_time_eq(hash, &calculated_hash))
 }
 
-fn run(context: &Context, state: &mut Argon2Result) -> Vec<u8> {
+fn run(context: &Context, state: &mut Argon2Result, hooks: &mut Hooks) -> Result<Option<Vec<u8>>> {
     let mut memory = Memory::new(context.config.lanes, context.lane_length);
     core::initialize(context, &mut memory, state);
-    core::fill_memory_blocks(context, &mut memory, state);
-    core::finalize(context, &memory)
+    state.flush(hooks.sink);
+    core::fill_memory_blocks(context, &mut memory, state, hooks)?;
+    if context.config.stop_at_iteration.is_some() {
+        state.set_partial(&memory);
+        return Ok(None);
+    }
+    let hash = core::finalize(context, &memory, state);
+    state.flush(hooks.sink);
+    Ok(Some(hash))
 }
 
 #[cfg(test)]
@@ -276,9 +490,589 @@ mod tests {
         let hash = hash_encoded(b"foo", b"abcdefghijklmnopqrstuvwxyz", &Config {
             lanes: 4, thread_mode: ThreadMode::Parallel,
             ..Config::default()
-        });
+        }).unwrap();
         */
         let hash = "$argon2i$v=19$m=4096,t=3,p=4$YWJjZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXo$BvBk2OaSofBHfbrUW61nHrWB/43xgfs/QJJ5DkMAd8I";
         verify_encoded(hash, b"foo").unwrap();
     }
+
+    #[test]
+    fn verify_encoded_accepts_reference_hashes() {
+        let hash = "$argon2i$v=19$m=4096,t=3,p=1$c29tZXNhbHQ\
+                    $iWh06vD8Fy27wf9npn6FXWiCX4K6pW6Ue1Bnzz07Z8A";
+        assert!(verify_encoded(hash, b"password").unwrap());
+        let hash = "$argon2i$v=19$m=4096,t=3,p=1$c29tZXNhbHQ\
+                    $OlcSvlN20Lz43sK3jhCJ9K04oejhiY0AmI+ck6nuETo";
+        assert!(verify_encoded_ext(hash, b"password", b"secret", b"ad").unwrap());
+    }
+
+    #[test]
+    fn hash_encoded_records_typed_trace() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            lanes: 2,
+            mem_cost: 32,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        let encoded = hash_encoded(b"password", b"somesalt", &config, &mut result)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.hash, encoded);
+
+        let events = &result.trace.events;
+        let count = |f: fn(&TraceEvent) -> bool| events.iter().filter(|e| f(e)).count();
+        // 2 lanes of 16 blocks, 2 passes, the first 2 blocks of each lane come from H0.
+        assert_eq!(count(|e| matches!(e, TraceEvent::BlockInit(_))), 4);
+        assert_eq!(
+            count(|e| matches!(e, TraceEvent::ReferenceSelection(_))),
+            60
+        );
+        assert_eq!(count(|e| matches!(e, TraceEvent::BlockFill(_))), 60);
+        match events.last() {
+            Some(TraceEvent::Finalization(f)) => assert_eq!(f.tag.len(), 32),
+            _ => panic!("last event is not a finalization"),
+        }
+    }
+
+    #[test]
+    fn hash_encoded_with_stop_at_iteration_returns_partial_result() {
+        use crate::trace::TraceEvent;
+
+        let config = Config {
+            lanes: 2,
+            mem_cost: 32,
+            time_cost: 3,
+            ..Config::default()
+        };
+        let mut full = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut full).unwrap();
+
+        let config = Config {
+            stop_at_iteration: Some(1),
+            ..config
+        };
+        let mut partial = Argon2Result::new();
+        let encoded = hash_encoded(b"password", b"somesalt", &config, &mut partial).unwrap();
+        assert_eq!(encoded, None);
+        assert_eq!(partial.hash, "");
+        assert!(partial.partial);
+        assert!(!full.partial);
+        assert!(full.memory.is_empty());
+
+        // The partial trace is the full trace up to the end of pass 1.
+        let events = &partial.trace.events;
+        assert_eq!(events[..], full.trace.events[..events.len()]);
+        assert!(events.iter().all(|e| match e {
+            TraceEvent::BlockFill(fill) => fill.pass <= 1,
+            TraceEvent::Finalization(_) => false,
+            _ => true,
+        }));
+
+        // The memory holds the last content written to every block.
+        assert_eq!(partial.memory.len(), 32);
+        for event in events {
+            if let TraceEvent::BlockFill(fill) = event {
+                if fill.pass == 1 {
+                    let slot = (fill.lane * 16 + fill.index) as usize;
+                    assert_eq!(partial.memory[slot], fill.content);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hash_encoded_with_short_salt_returns_correct_error() {
+        let mut result = Argon2Result::new();
+        let res = hash_encoded(b"password", b"salt", &Config::default(), &mut result);
+        assert_eq!(res, Err(crate::error::Error::SaltTooShort));
+        assert!(result.trace.events.is_empty());
+    }
+
+    #[test]
+    fn hash_encoded_bytes_js_hashes_binary_inputs() {
+        let pwd = [0u8, 159, 255, 10];
+        let salt = [0u8, 1, 2, 3, 252, 253, 254, 255];
+        let config_json = r#"{"memory": "32", "iterations": "1"}"#;
+        let json = hash_encoded_bytes_js(&pwd, &salt, config_json.to_string(), None, None).unwrap();
+        let result = Argon2Result::from_json(&json).unwrap();
+
+        let mut expected = Argon2Result::new();
+        let config = Config::from_json(config_json).unwrap();
+        hash_encoded(&pwd, &salt, &config, &mut expected).unwrap();
+        assert_eq!(result.hash, expected.hash);
+        assert!(verify_encoded_bytes_js(result.hash, &pwd, None, None).unwrap());
+    }
+
+    #[test]
+    fn hash_encoded_bytes_js_uses_secret_and_ad() {
+        let config_json = r#"{"memory": "32", "iterations": "1", "encoding": "base64"}"#;
+        let secret = Some(b"secret".to_vec());
+        let ad = Some(vec![0u8, 255]);
+        let json = hash_encoded_bytes_js(
+            b"password",
+            b"somesalt",
+            config_json.to_string(),
+            secret.clone(),
+            ad.clone(),
+        )
+        .unwrap();
+        let hash = Argon2Result::from_json(&json).unwrap().hash;
+
+        assert!(verify_encoded_ext(&hash, b"password", b"secret", &[0, 255]).unwrap());
+        assert!(verify_encoded_bytes_js(hash.clone(), b"password", secret, ad).unwrap());
+        assert!(!verify_encoded_bytes_js(hash, b"password", None, None).unwrap());
+    }
+
+    #[test]
+    fn hash_raw_ignores_stop_at_iteration() {
+        let config = Config {
+            stop_at_iteration: Some(0),
+            ..Config::default()
+        };
+        let hash = hash_raw(b"password", b"somesalt", &config).unwrap();
+        assert_eq!(
+            hash,
+            hash_raw(b"password", b"somesalt", &Config::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn hash_encoded_records_every_filled_block() {
+        use crate::trace::{BlockCoord, TraceEvent, TraceLevel};
+
+        let config = Config {
+            lanes: 2,
+            mem_cost: 32,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+
+        let lane_length = 16;
+        let mut filled = vec![0; 2 * 2 * lane_length as usize];
+        for event in &result.trace.events {
+            if let TraceEvent::BlockFill(fill) = event {
+                let prev_index = (fill.index + lane_length - 1) % lane_length;
+                assert_eq!(
+                    fill.prev,
+                    BlockCoord {
+                        lane: fill.lane,
+                        index: prev_index
+                    }
+                );
+                assert_eq!(fill.with_xor, fill.pass > 0);
+                assert_eq!(fill.content.len(), 1024);
+                assert_eq!(fill.slice, fill.index / 4);
+                let slot = (fill.pass * 2 + fill.lane) * lane_length + fill.index;
+                filled[slot as usize] += 1;
+            }
+        }
+        // Every block is filled exactly once per pass, except the first two
+        // blocks of each lane in the first pass.
+        for (slot, count) in filled.iter().enumerate() {
+            let expected = if slot < 32 && slot % 16 < 2 { 0 } else { 1 };
+            assert_eq!(*count, expected, "block slot {}", slot);
+        }
+    }
+
+    #[cfg(feature = "crossbeam-utils")]
+    #[test]
+    fn parallel_trace_matches_sequential_trace() {
+        use crate::trace::TraceLevel;
+
+        for variant in [Variant::Argon2d, Variant::Argon2i, Variant::Argon2id] {
+            let config = Config {
+                variant,
+                lanes: 4,
+                mem_cost: 64,
+                time_cost: 2,
+                thread_mode: ThreadMode::Sequential,
+                trace: TraceOptions::new(TraceLevel::Blocks),
+                ..Config::default()
+            };
+            let mut sequential = Argon2Result::new();
+            hash_encoded(b"password", b"somesalt", &config, &mut sequential).unwrap();
+
+            let config = Config {
+                thread_mode: ThreadMode::Parallel,
+                ..config
+            };
+            let mut parallel = Argon2Result::new();
+            hash_encoded(b"password", b"somesalt", &config, &mut parallel).unwrap();
+
+            assert!(!parallel.trace.events.is_empty());
+            assert_eq!(parallel, sequential);
+        }
+    }
+
+    #[test]
+    fn hash_encoded_records_compression_internals() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 2,
+            trace: TraceOptions {
+                level: TraceLevel::QuarterRounds,
+                ..TraceOptions::default()
+            },
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+
+        let events = &result.trace.events;
+        let mut compressions = 0;
+        for (event, next) in events.iter().zip(events.iter().skip(1)) {
+            let (compression, fill) = match (event, next) {
+                (TraceEvent::Compression(c), TraceEvent::BlockFill(f)) => (c, f),
+                (TraceEvent::Compression(_), _) => panic!("compression not followed by fill"),
+                _ => continue,
+            };
+            compressions += 1;
+            assert_eq!(
+                (compression.pass, compression.index),
+                (fill.pass, fill.index)
+            );
+            assert_eq!(compression.output, fill.content);
+            let z_xor_r: Vec<u8> = compression
+                .z
+                .iter()
+                .zip(compression.r.iter())
+                .map(|(z, r)| z ^ r)
+                .collect();
+            assert_eq!(*compression.output == z_xor_r[..], !fill.with_xor);
+
+            // The quarter rounds chain R into Q into Z.
+            let rounds = compression.rounds.as_ref().unwrap();
+            assert_eq!(rounds.len(), 128);
+            let word = |block: &[u8], w: u32| block[w as usize * 8..w as usize * 8 + 8].to_vec();
+            let words = |block: &[u8], ws: [u32; 4]| -> Vec<u8> {
+                ws.iter().flat_map(|w| word(block, *w)).collect()
+            };
+            assert_eq!(rounds[0].words, [0, 4, 8, 12]);
+            assert_eq!(*rounds[0].input, words(&compression.r, rounds[0].words)[..]);
+            assert_eq!(
+                *rounds[63].output,
+                words(&compression.q, rounds[63].words)[..]
+            );
+            assert_eq!(rounds[64].words, [0, 32, 64, 96]);
+            assert_eq!(
+                *rounds[127].output,
+                words(&compression.z, rounds[127].words)[..]
+            );
+        }
+        // 6 blocks in the first pass and 8 in the second.
+        assert_eq!(compressions, 14);
+    }
+
+    #[test]
+    fn hash_encoded_records_address_blocks() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        for (variant, expected) in [
+            (Variant::Argon2d, 0),
+            (Variant::Argon2i, 16),
+            (Variant::Argon2id, 4),
+        ] {
+            let config = Config {
+                variant,
+                mem_cost: 1024,
+                time_cost: 2,
+                trace: TraceOptions::new(TraceLevel::Blocks),
+                ..Config::default()
+            };
+            let mut result = Argon2Result::new();
+            hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+
+            let blocks: Vec<_> = result
+                .trace
+                .events
+                .iter()
+                .filter_map(|e| match e {
+                    TraceEvent::AddressBlock(block) => Some(block),
+                    _ => None,
+                })
+                .collect();
+            // Segments of 256 blocks need two address blocks each.
+            assert_eq!(blocks.len(), expected, "{:?}", variant);
+            for (i, block) in blocks.iter().enumerate() {
+                assert_eq!(block.counter, i as u32 % 2 + 1);
+                assert_eq!(block.slice, i as u32 / 2 % 4);
+                assert_eq!(block.pass, i as u32 / 8);
+                assert_eq!(block.memory_blocks, 1024);
+                assert_eq!(block.time_cost, 2);
+                assert_eq!(block.variant, variant.as_u32());
+                assert_eq!(block.addresses.len(), 128);
+            }
+        }
+    }
+
+    #[test]
+    fn hash_encoded_records_reference_mapping() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            lanes: 4,
+            mem_cost: 64,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+
+        let lane_length = 16;
+        let mut count = 0;
+        for event in result.trace.events.iter() {
+            if let TraceEvent::ReferenceSelection(s) = event {
+                count += 1;
+                if s.pass == 0 && s.slice == 0 {
+                    assert!(s.same_lane);
+                } else {
+                    assert_eq!(s.ref_lane, s.j2 % 4);
+                }
+                assert_eq!(s.same_lane, s.ref_lane == s.lane);
+                let x = (s.j1 as u64 * s.j1 as u64) >> 32;
+                let y = (s.reference_area_size as u64 * x) >> 32;
+                let z = s.reference_area_size as u64 - 1 - y;
+                assert_eq!(s.relative_position as u64, z);
+                assert_eq!(
+                    s.ref_index,
+                    (s.start_position + s.relative_position) % lane_length
+                );
+            }
+        }
+        assert_eq!(count, 2 * 64 - 2 * 4);
+    }
+
+    #[test]
+    fn hash_encoded_records_prehash_fields() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 1,
+            secret: b"key",
+            ad: b"data",
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+
+        let prehash = match &result.trace.events[0] {
+            TraceEvent::Prehash(prehash) => prehash,
+            event => panic!("unexpected first event {:?}", event),
+        };
+        let names: Vec<&str> = prehash.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "lanes",
+                "hash_length",
+                "mem_cost",
+                "time_cost",
+                "version",
+                "variant",
+                "pwd_length",
+                "pwd",
+                "salt_length",
+                "salt",
+                "secret_length",
+                "secret",
+                "ad_length",
+                "ad"
+            ]
+        );
+        assert_eq!(prehash.fields[2].value, Some(8));
+        assert_eq!(*prehash.fields[2].bytes, [8, 0, 0, 0]);
+        assert_eq!(*prehash.fields[11].bytes, *b"key");
+        assert_eq!(prehash.fields[12].value, Some(4));
+
+        let input: Vec<u8> = prehash
+            .fields
+            .iter()
+            .flat_map(|f| f.bytes.to_vec())
+            .collect();
+        let digest = blake2b_simd::Params::new().hash(&input);
+        assert_eq!(*prehash.digest, *digest.as_bytes());
+    }
+
+    #[test]
+    fn hash_encoded_records_hprime_chains() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 1,
+            hash_length: 100,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+
+        let chains: Vec<_> = result
+            .trace
+            .events
+            .iter()
+            .filter_map(|e| match e {
+                TraceEvent::Hprime(hprime) => Some(hprime),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(chains.len(), 3);
+
+        // A block of 1024 bytes takes 30 parts of 32 bytes and a last V of
+        // 64 bytes, a tag of 100 bytes 2 parts of 32 bytes and 36 bytes.
+        for (hprime, length, count) in [(chains[0], 1024, 31), (chains[2], 100, 3)] {
+            assert_eq!(hprime.length, length);
+            assert_eq!(hprime.v.len(), count);
+            assert_eq!(hprime.parts.len(), count);
+            let mut output = Vec::new();
+            for part in hprime.parts.iter() {
+                assert_eq!(part.offset as usize, output.len());
+                let v = &hprime.v[part.v as usize];
+                output.extend_from_slice(&v[..part.length as usize]);
+            }
+            assert_eq!(output, *hprime.output);
+        }
+        assert_eq!(chains[2].input.len(), 1024);
+        assert_eq!(chains[2].parts[2].length, 36);
+        assert_eq!(chains[2].v[2].len(), 36);
+    }
+
+    #[test]
+    fn hash_encoded_records_last_blocks_and_tag() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            lanes: 3,
+            mem_cost: 24,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        let encoded = hash_encoded(b"password", b"somesalt", &config, &mut result)
+            .unwrap()
+            .unwrap();
+
+        let finalization = match result.trace.events.last() {
+            Some(TraceEvent::Finalization(finalization)) => finalization,
+            event => panic!("unexpected last event {:?}", event),
+        };
+        assert_eq!(finalization.blocks.len(), 3);
+        let mut accumulator = vec![0u8; 1024];
+        for (lane, last) in finalization.blocks.iter().enumerate() {
+            assert_eq!(last.block.lane, lane as u32);
+            assert_eq!(last.block.index, 7);
+            let filled = result.trace.events.iter().rev().find_map(|e| match e {
+                TraceEvent::BlockFill(fill) if fill.lane == lane as u32 && fill.index == 7 => {
+                    Some(fill)
+                }
+                _ => None,
+            });
+            assert_eq!(last.content, filled.unwrap().content);
+            for (a, b) in accumulator.iter_mut().zip(last.content.iter()) {
+                *a ^= b;
+            }
+            assert_eq!(*last.accumulator, accumulator[..]);
+        }
+        let decoded = crate::encoding::decode_string(&encoded).unwrap();
+        assert_eq!(*finalization.tag, decoded.hash[..]);
+    }
+
+    #[test]
+    fn hash_encoded_records_nothing_when_tracing_is_off() {
+        let config = Config {
+            mem_cost: 16,
+            time_cost: 2,
+            trace: TraceOptions::off(),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        let encoded = hash_encoded(b"password", b"somesalt", &config, &mut result)
+            .unwrap()
+            .unwrap();
+        assert!(result.trace.events.is_empty());
+        assert!(verify_encoded(&encoded, b"password").unwrap());
+    }
+
+    #[test]
+    fn hash_encoded_records_only_references() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            variant: Variant::Argon2id,
+            mem_cost: 16,
+            time_cost: 1,
+            trace: TraceOptions {
+                level: TraceLevel::References,
+                ..TraceOptions::default()
+            },
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+        assert_eq!(result.trace.events.len(), 2 + 16 - 2);
+        assert!(result.trace.events.iter().all(|e| matches!(
+            e,
+            TraceEvent::AddressBlock(_) | TraceEvent::ReferenceSelection(_)
+        )));
+    }
+
+    #[test]
+    fn hash_encoded_records_selected_blocks() {
+        use crate::trace::{TraceEvent, TraceLevel};
+
+        let config = Config {
+            lanes: 2,
+            mem_cost: 64,
+            time_cost: 3,
+            trace: TraceOptions {
+                level: TraceLevel::Compression,
+                pass: Some(1..2),
+                lane: Some(1..2),
+                slice: Some(2..3),
+                every: 2,
+                ..TraceOptions::default()
+            },
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        let encoded = hash_encoded(b"password", b"somesalt", &config, &mut result)
+            .unwrap()
+            .unwrap();
+
+        let mut fills = 0;
+        for event in result.trace.events.iter() {
+            let (pass, lane, slice, index) = match event {
+                TraceEvent::ReferenceSelection(e) => (e.pass, e.lane, e.slice, e.index),
+                TraceEvent::Compression(e) => (e.pass, e.lane, e.slice, e.index),
+                TraceEvent::BlockFill(e) => {
+                    fills += 1;
+                    (e.pass, e.lane, e.slice, e.index)
+                }
+                TraceEvent::AddressBlock(e) => (e.pass, e.lane, e.slice, 0),
+                _ => continue,
+            };
+            assert_eq!((pass, lane, slice), (1, 1, 2));
+            assert_eq!(index % 2, 0);
+        }
+        // Slice 2 of a lane of 32 blocks holds indices 16 to 23.
+        assert_eq!(fills, 4);
+        assert!(matches!(
+            result.trace.events.last(),
+            Some(TraceEvent::Finalization(_))
+        ));
+        assert!(verify_encoded(&encoded, b"password").unwrap());
+    }
 }
@@ -0,0 +1,355 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::common;
+use crate::config::Config;
+use crate::context::Context;
+use crate::core::{self, Position};
+use crate::error::Error;
+use crate::memory::Memory;
+use crate::result::{Argon2Result, JsResult, Result};
+use crate::snapshot::SnapshotMode;
+use crate::trace::TraceOptions;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use wasm_bindgen::prelude::*;
+
+/// Differences between the memories of two runs after every pass. Blocks
+/// are stored lane by lane.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Avalanche {
+    /// The number of lanes.
+    pub lanes: u32,
+
+    /// The number of blocks in a lane.
+    pub lane_length: u32,
+
+    /// The number of passes compared, the largest of the two runs.
+    pub passes: u32,
+
+    /// The number of differing bits of every block after the last pass.
+    pub distances: Vec<u32>,
+
+    /// The first pass after which each block differs, `None` for the blocks
+    /// that never do.
+    pub first_divergence: Vec<Option<u32>>,
+
+    /// The first pass after which the memories differ, `None` when they
+    /// never do.
+    pub first_divergent_pass: Option<u32>,
+
+    /// The total number of differing bits after every pass.
+    pub pass_distances: Vec<u64>,
+}
+
+impl Avalanche {
+    /// Runs Argon2 with both sets of inputs, without tracing, and compares
+    /// the memories after every pass. A run with fewer passes keeps its
+    /// memory as is after its last pass.
+    ///
+    /// The inputs usually differ in one field only, such as a single byte of
+    /// the password, to show how far the change spreads.
+    pub fn compare(
+        first_pwd: &[u8],
+        first_salt: &[u8],
+        first_config: &Config,
+        second_pwd: &[u8],
+        second_salt: &[u8],
+        second_config: &Config,
+    ) -> Result<Avalanche> {
+        let first = Context::new(quiet(first_config), first_pwd, first_salt)?;
+        let second = Context::new(quiet(second_config), second_pwd, second_salt)?;
+        if first.config.lanes != second.config.lanes || first.lane_length != second.lane_length {
+            return Err(Error::MemoryLayoutMismatch);
+        }
+
+        let contexts = [&first, &second];
+        let mut memories = [
+            Memory::new(first.config.lanes, first.lane_length),
+            Memory::new(second.config.lanes, second.lane_length),
+        ];
+        let mut state = Argon2Result::new();
+        for (context, memory) in contexts.iter().zip(memories.iter_mut()) {
+            core::initialize(context, memory, &mut state);
+        }
+
+        let passes = core::passes(&first).max(core::passes(&second));
+        let blocks = (first.config.lanes * first.lane_length) as usize;
+        let mut avalanche = Avalanche {
+            lanes: first.config.lanes,
+            lane_length: first.lane_length,
+            passes,
+            distances: vec![0; blocks],
+            first_divergence: vec![None; blocks],
+            first_divergent_pass: None,
+            pass_distances: Vec::with_capacity(passes as usize),
+        };
+        for pass in 0..passes {
+            for (context, memory) in contexts.iter().zip(memories.iter_mut()) {
+                if pass < core::passes(context) {
+                    fill_pass(context, memory, pass, &mut state);
+                }
+            }
+            avalanche.compare_pass(pass, &memories[0], &memories[1]);
+        }
+        Ok(avalanche)
+    }
+
+    fn compare_pass(&mut self, pass: u32, first: &Memory, second: &Memory) {
+        let mut total = 0u64;
+        let blocks = first.blocks().iter().zip(second.blocks().iter());
+        for (i, (a, b)) in blocks.enumerate() {
+            let distance = a.hamming_distance(b);
+            self.distances[i] = distance;
+            if distance > 0 && self.first_divergence[i].is_none() {
+                self.first_divergence[i] = Some(pass);
+            }
+            total += distance as u64;
+        }
+        if total > 0 && self.first_divergent_pass.is_none() {
+            self.first_divergent_pass = Some(pass);
+        }
+        self.pass_distances.push(total);
+    }
+}
+
+/// Compares the memories of two runs, each given as a JSON object with the
+/// `pwd`, `salt`, `secret` and `ad` strings next to the keys accepted by
+/// `hash_encoded_js`. Returns the `Avalanche` as JSON. The `pwd` and `salt`
+/// are required.
+#[wasm_bindgen]
+pub fn avalanche_js(first_json: String, second_json: String) -> JsResult<String> {
+    let first: Value = serde_json::from_str(&first_json).map_err(Error::from)?;
+    let second: Value = serde_json::from_str(&second_json).map_err(Error::from)?;
+    let (first_pwd, first_salt, first_config) = inputs(&first_json, &first)?;
+    let (second_pwd, second_salt, second_config) = inputs(&second_json, &second)?;
+    let avalanche = Avalanche::compare(
+        first_pwd,
+        first_salt,
+        &first_config,
+        second_pwd,
+        second_salt,
+        &second_config,
+    )?;
+    Ok(serde_json::to_string(&avalanche).map_err(Error::from)?)
+}
+
+fn quiet<'a>(config: &Config<'a>) -> Config<'a> {
+    Config {
+        trace: TraceOptions::off(),
+        snapshot: SnapshotMode::Off,
+        ..config.clone()
+    }
+}
+
+fn fill_pass(context: &Context, memory: &mut Memory, pass: u32, state: &mut Argon2Result) {
+    for slice in 0..common::SYNC_POINTS {
+        for lane in 0..context.config.lanes {
+            let position = Position {
+                pass,
+                lane,
+                slice,
+                index: 0,
+            };
+            core::fill_segment(context, &position, context.segment_length, memory, state);
+        }
+    }
+}
+
+fn inputs<'a>(json: &'a str, value: &'a Value) -> Result<(&'a [u8], &'a [u8], Config<'a>)> {
+    let mut config = Config::from_json(json)?;
+    config.secret = field(value, "secret", false)?;
+    config.ad = field(value, "ad", false)?;
+    let pwd = field(value, "pwd", true)?;
+    let salt = field(value, "salt", true)?;
+    Ok((pwd, salt, config))
+}
+
+/// Gets a string field as bytes, failing with `Error::IncorrectArgument`
+/// when it is not a string. A missing field is empty unless `required`.
+fn field<'a>(value: &'a Value, key: &str, required: bool) -> Result<&'a [u8]> {
+    match &value[key] {
+        Value::String(string) => Ok(string.as_bytes()),
+        Value::Null if !required => Ok(&[]),
+        _ => Err(Error::IncorrectArgument),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::avalanche::{avalanche_js, inputs, Avalanche};
+    use crate::config::Config;
+    use crate::error::Error;
+    use crate::thread_mode::ThreadMode;
+
+    fn config() -> Config<'static> {
+        Config {
+            lanes: 2,
+            mem_cost: 32,
+            time_cost: 2,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn compare_with_same_inputs_finds_no_difference() {
+        let config = config();
+        let avalanche = Avalanche::compare(
+            b"password",
+            b"somesalt",
+            &config,
+            b"password",
+            b"somesalt",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(avalanche.passes, 2);
+        assert_eq!(avalanche.distances, vec![0; 32]);
+        assert_eq!(avalanche.first_divergence, vec![None; 32]);
+        assert_eq!(avalanche.first_divergent_pass, None);
+        assert_eq!(avalanche.pass_distances, vec![0, 0]);
+    }
+
+    #[test]
+    fn compare_with_changed_password_byte_changes_every_block_at_once() {
+        let config = config();
+        let avalanche = Avalanche::compare(
+            b"password",
+            b"somesalt",
+            &config,
+            b"pastword",
+            b"somesalt",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(avalanche.first_divergent_pass, Some(0));
+        assert_eq!(avalanche.first_divergence, vec![Some(0); 32]);
+        let mean = avalanche.pass_distances[1] / 32;
+        assert!(mean > 3900 && mean < 4300, "mean distance {}", mean);
+    }
+
+    #[test]
+    fn compare_with_different_secret_changes_every_block() {
+        let first = config();
+        let second = Config {
+            secret: b"secret",
+            ..config()
+        };
+        let avalanche = Avalanche::compare(
+            b"password",
+            b"somesalt",
+            &first,
+            b"password",
+            b"somesalt",
+            &second,
+        )
+        .unwrap();
+        assert!(avalanche.distances.iter().all(|&d| d > 0));
+    }
+
+    #[test]
+    fn compare_finds_first_pass_of_divergence() {
+        let first = config();
+        let second = Config {
+            stop_at_iteration: Some(0),
+            ..config()
+        };
+        let avalanche = Avalanche::compare(
+            b"password",
+            b"somesalt",
+            &first,
+            b"password",
+            b"somesalt",
+            &second,
+        )
+        .unwrap();
+        assert_eq!(avalanche.first_divergent_pass, Some(1));
+        assert_eq!(avalanche.first_divergence, vec![Some(1); 32]);
+        assert_eq!(avalanche.pass_distances[0], 0);
+    }
+
+    #[test]
+    fn compare_ignores_thread_mode() {
+        let first = config();
+        let second = Config {
+            thread_mode: ThreadMode::Parallel,
+            ..config()
+        };
+        let avalanche = Avalanche::compare(
+            b"password",
+            b"somesalt",
+            &first,
+            b"password",
+            b"somesalt",
+            &second,
+        )
+        .unwrap();
+        assert_eq!(avalanche.first_divergent_pass, None);
+    }
+
+    #[test]
+    fn compare_with_different_layouts_returns_correct_error() {
+        let first = config();
+        let second = Config {
+            lanes: 1,
+            ..config()
+        };
+        assert_eq!(
+            Avalanche::compare(
+                b"password",
+                b"somesalt",
+                &first,
+                b"password",
+                b"somesalt",
+                &second
+            ),
+            Err(Error::MemoryLayoutMismatch)
+        );
+    }
+
+    #[test]
+    fn avalanche_js_reads_inputs_and_config() {
+        let first = r#"{"pwd": "password", "salt": "somesalt", "memory": "16", "iterations": "2"}"#;
+        let second = r#"{"pwd": "password", "salt": "somesalt", "ad": "x", "memory": "16", "iterations": "2"}"#;
+        let json = avalanche_js(first.to_string(), second.to_string()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["lane_length"], 16);
+        assert_eq!(value["first_divergent_pass"], 0);
+        assert_eq!(value["pass_distances"].as_array().unwrap().len(), 2);
+
+        let json = avalanche_js(first.to_string(), first.to_string()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(value["first_divergent_pass"].is_null());
+    }
+
+    #[test]
+    fn inputs_rejects_missing_or_non_string_fields() {
+        for json in [
+            r#"{"salt": "somesalt"}"#,
+            r#"{"pwd": 1, "salt": "somesalt"}"#,
+            r#"{"pwd": "password"}"#,
+            r#"{"pwd": "password", "salt": "somesalt", "ad": [1]}"#,
+        ] {
+            let value: serde_json::Value = serde_json::from_str(json).unwrap();
+            assert_eq!(
+                inputs(json, &value).err(),
+                Some(Error::IncorrectArgument),
+                "{}",
+                json
+            );
+        }
+
+        let json = r#"{"pwd": "password", "salt": "somesalt"}"#;
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        let (pwd, salt, config) = inputs(json, &value).unwrap();
+        assert_eq!((pwd, salt), (&b"password"[..], &b"somesalt"[..]));
+        assert!(config.secret.is_empty() && config.ad.is_empty());
+    }
+}
@@ -39,13 +39,22 @@ impl Block {
         }
     }
 
+    /// Gets the number of bits that differ between self and other.
+    pub fn hamming_distance(&self, other: &Block) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(s, o)| (s ^ o).count_ones())
+            .sum()
+    }
+
     /// Creates a new block filled with zeros.
     pub fn zero() -> Block {
         Block([0u64; common::QWORDS_IN_BLOCK])
     }
 }
 
-impl<'a> BitXorAssign<&'a Block> for Block {
+impl BitXorAssign<&Block> for Block {
     fn bitxor_assign(&mut self, rhs: &Block) {
         for (s, r) in self.0.iter_mut().zip(rhs.0.iter()) {
             *s ^= *r
@@ -137,6 +146,16 @@ mod tests {
         assert_eq!(copy, orig);
     }
 
+    #[test]
+    fn hamming_distance_counts_differing_bits() {
+        let lhs = Block([0u64; common::QWORDS_IN_BLOCK]);
+        let mut rhs = Block([0u64; common::QWORDS_IN_BLOCK]);
+        assert_eq!(lhs.hamming_distance(&rhs), 0);
+        rhs[0] = 0b1011;
+        rhs[127] = u64::MAX;
+        assert_eq!(lhs.hamming_distance(&rhs), 67);
+    }
+
     #[test]
     fn zero_creates_block_will_all_zeros() {
         let expected = Block([0u64; common::QWORDS_IN_BLOCK]);
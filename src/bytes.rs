@@ -0,0 +1,242 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::error::Error;
+use crate::result::Result;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::ser::{CompactFormatter, Formatter};
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::str::{self, FromStr};
+
+/// The encoding used for byte payloads when a trace is (de)serialized.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteEncoding {
+    /// Lowercase hexadecimal string.
+    #[default]
+    Hex,
+
+    /// Standard base64 string with padding.
+    Base64,
+
+    /// Array of numbers, suitable for `Uint8Array.from`.
+    Raw,
+}
+
+impl ByteEncoding {
+    /// Gets the string slice representation of the encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ByteEncoding::Hex => "hex",
+            ByteEncoding::Base64 => "base64",
+            ByteEncoding::Raw => "raw",
+        }
+    }
+}
+
+impl FromStr for ByteEncoding {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(ByteEncoding::Hex),
+            "base64" => Ok(ByteEncoding::Base64),
+            "raw" => Ok(ByteEncoding::Raw),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Byte payload of a trace event, such as a block content or a digest.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Deref for Bytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Bytes {
+        Bytes(bytes.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Bytes {
+        Bytes(bytes)
+    }
+}
+
+impl Bytes {
+    /// Decodes a payload that was read from a string written in `encoding`.
+    /// Payloads read from arrays of numbers are left as they are.
+    pub fn decode(&mut self, encoding: ByteEncoding) -> Result<()> {
+        let decoded = match encoding {
+            ByteEncoding::Hex => str::from_utf8(&self.0).ok().and_then(decode_hex),
+            ByteEncoding::Base64 => str::from_utf8(&self.0)
+                .ok()
+                .and_then(|text| base64::decode(text).ok()),
+            ByteEncoding::Raw => return Ok(()),
+        };
+        self.0 = decoded.ok_or(Error::DecodingFail)?;
+        Ok(())
+    }
+}
+
+/// Serializes as an array of numbers, unless written by `to_json` with
+/// another encoding.
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+/// Deserializes from an array of numbers, or from a string that is kept as
+/// its text until `decode` is called with its encoding.
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Bytes, D::Error> {
+        deserializer.deserialize_any(BytesVisitor)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an array of bytes or an encoded string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Bytes, E> {
+        Ok(Bytes(v.as_bytes().to_vec()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> std::result::Result<Bytes, E> {
+        Ok(Bytes(v.to_vec()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Bytes, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(Bytes(bytes))
+    }
+}
+
+/// Serializes a value to JSON with its byte payloads in `encoding`.
+pub fn to_json<T: ?Sized + Serialize>(value: &T, encoding: ByteEncoding) -> String {
+    let mut json = Vec::new();
+    let mut serializer =
+        serde_json::Serializer::with_formatter(&mut json, EncodingFormatter(encoding));
+    value.serialize(&mut serializer).unwrap();
+    String::from_utf8(json).unwrap()
+}
+
+/// Compact JSON formatter writing byte arrays in an encoding.
+struct EncodingFormatter(ByteEncoding);
+
+impl Formatter for EncodingFormatter {
+    fn write_byte_array<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        value: &[u8],
+    ) -> io::Result<()> {
+        let encoded = match self.0 {
+            ByteEncoding::Hex => encode_hex(value),
+            ByteEncoding::Base64 => base64::encode(value),
+            ByteEncoding::Raw => return CompactFormatter.write_byte_array(writer, value),
+        };
+        // Neither encoding produces characters that need escaping.
+        writer.write_all(b"\"")?;
+        writer.write_all(encoded.as_bytes())?;
+        writer.write_all(b"\"")
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        out.push(DIGITS[(b >> 4) as usize] as char);
+        out.push(DIGITS[(b & 0x0F) as usize] as char);
+    }
+    out
+}
+
+fn decode_hex(str: &str) -> Option<Vec<u8>> {
+    let pairs = str.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| {
+            let hi = (pair[0] as char).to_digit(16)?;
+            let lo = (pair[1] as char).to_digit(16)?;
+            Some((hi << 4 | lo) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::bytes::{to_json, ByteEncoding, Bytes};
+    use crate::error::Error;
+
+    fn round_trip(encoding: ByteEncoding, expected: &str) {
+        let bytes = Bytes(vec![0x00, 0x7f, 0x80, 0xff, 0xfe]);
+        let json = to_json(&bytes, encoding);
+        assert_eq!(json, expected);
+        let mut parsed: Bytes = serde_json::from_str(&json).unwrap();
+        parsed.decode(encoding).unwrap();
+        assert_eq!(parsed, bytes);
+    }
+
+    #[test]
+    fn hex_round_trip_is_lossless() {
+        round_trip(ByteEncoding::Hex, r#""007f80fffe""#);
+    }
+
+    #[test]
+    fn base64_round_trip_is_lossless() {
+        round_trip(ByteEncoding::Base64, r#""AH+A//4=""#);
+    }
+
+    #[test]
+    fn raw_round_trip_is_lossless() {
+        round_trip(ByteEncoding::Raw, "[0,127,128,255,254]");
+    }
+
+    #[test]
+    fn invalid_hex_returns_error() {
+        let mut bytes: Bytes = serde_json::from_str(r#""0g""#).unwrap();
+        assert_eq!(bytes.decode(ByteEncoding::Hex), Err(Error::DecodingFail));
+    }
+
+    #[test]
+    fn nested_payloads_use_the_encoding() {
+        let value = (Some(vec![Bytes(vec![1])]), Bytes(vec![2, 3]), vec![4u8]);
+        let json = to_json(&value, ByteEncoding::Base64);
+        assert_eq!(json, r#"[["AQ=="],"AgM=",[4]]"#);
+    }
+
+    #[test]
+    fn plain_serialization_uses_arrays() {
+        let bytes = Bytes(vec![1, 2]);
+        assert_eq!(serde_json::to_string(&bytes).unwrap(), "[1,2]");
+        assert_eq!(serde_json::from_str::<Bytes>("[1,2]").unwrap(), bytes);
+    }
+}
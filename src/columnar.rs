@@ -0,0 +1,535 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::argon2::hash_encoded_with_sink;
+use crate::common;
+use crate::config::Config;
+use crate::error::Error;
+use crate::result::{Argon2Result, JsResult, Result};
+use crate::sink::TraceSink;
+use crate::trace::{Trace, TraceEvent, TraceLevel};
+use js_sys::{Uint32Array, Uint8Array};
+use std::panic;
+
+use wasm_bindgen::prelude::*;
+
+/// The magic bytes at the start of a columnar trace.
+pub const MAGIC: [u8; 4] = *b"A2TC";
+
+/// The version of the columnar trace layout written by `to_bytes`.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The reference column value of the initial blocks, which have none.
+pub const NO_REFERENCE: u32 = u32::MAX;
+
+const HEADER_LENGTH: usize = 16;
+const COLUMNS: usize = 6;
+const FLAG_CONTENT: u32 = 1;
+
+/// Columnar layout of the blocks written during a run, one row per initial
+/// or filled block in trace order.
+///
+/// The binary form is little-endian: the magic bytes, the format version,
+/// the number of rows and the flags, followed by the `pass`, `lane`,
+/// `slice`, `index`, `ref_lane` and `ref_index` columns of u32 values and,
+/// when flag bit 0 is set, the 1024-byte content of every row.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TraceColumns {
+    /// The pass the block was written in.
+    #[wasm_bindgen(skip)]
+    pub pass: Vec<u32>,
+
+    /// The lane of the block.
+    #[wasm_bindgen(skip)]
+    pub lane: Vec<u32>,
+
+    /// The slice of the block.
+    #[wasm_bindgen(skip)]
+    pub slice: Vec<u32>,
+
+    /// The index of the block within the lane.
+    #[wasm_bindgen(skip)]
+    pub index: Vec<u32>,
+
+    /// The lane of the reference block, `NO_REFERENCE` for initial blocks.
+    #[wasm_bindgen(skip)]
+    pub ref_lane: Vec<u32>,
+
+    /// The index of the reference block, `NO_REFERENCE` for initial blocks.
+    #[wasm_bindgen(skip)]
+    pub ref_index: Vec<u32>,
+
+    /// The concatenated block contents, when recorded.
+    #[wasm_bindgen(skip)]
+    pub content: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl TraceColumns {
+    /// Creates the columns from a result returned by `hash_encoded_js`.
+    #[wasm_bindgen(constructor)]
+    pub fn from_result_json(result_json: String, with_content: bool) -> JsResult<TraceColumns> {
+        let result = Argon2Result::from_json(&result_json)?;
+        Ok(TraceColumns::from_trace(&result.trace, with_content))
+    }
+
+    /// Reads the columns from their binary form.
+    pub fn read(bytes: &[u8]) -> JsResult<TraceColumns> {
+        Ok(TraceColumns::from_bytes(bytes)?)
+    }
+
+    /// Gets the number of rows.
+    pub fn len(&self) -> usize {
+        self.pass.len()
+    }
+
+    /// Returns whether there are no rows.
+    pub fn is_empty(&self) -> bool {
+        self.pass.is_empty()
+    }
+
+    /// Writes the columns in their binary form.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let rows = self.len();
+        let content_length = self.content.as_ref().map_or(0, |c| c.len());
+        let mut out = Vec::with_capacity(HEADER_LENGTH + rows * COLUMNS * 4 + content_length);
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(rows as u32).to_le_bytes());
+        let flags = if self.content.is_some() {
+            FLAG_CONTENT
+        } else {
+            0
+        };
+        out.extend_from_slice(&flags.to_le_bytes());
+        for column in self.columns().iter() {
+            for value in column.iter() {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        if let Some(content) = &self.content {
+            out.extend_from_slice(content);
+        }
+        out
+    }
+
+    /// Gets a view of the pass column. The view is invalidated when the
+    /// wasm memory grows, so copy it before calling into wasm again.
+    #[wasm_bindgen(js_name = pass)]
+    pub fn pass_view(&self) -> Uint32Array {
+        view_u32(&self.pass)
+    }
+
+    /// Gets a view of the lane column.
+    #[wasm_bindgen(js_name = lane)]
+    pub fn lane_view(&self) -> Uint32Array {
+        view_u32(&self.lane)
+    }
+
+    /// Gets a view of the slice column.
+    #[wasm_bindgen(js_name = slice)]
+    pub fn slice_view(&self) -> Uint32Array {
+        view_u32(&self.slice)
+    }
+
+    /// Gets a view of the index column.
+    #[wasm_bindgen(js_name = index)]
+    pub fn index_view(&self) -> Uint32Array {
+        view_u32(&self.index)
+    }
+
+    /// Gets a view of the reference lane column.
+    #[wasm_bindgen(js_name = refLane)]
+    pub fn ref_lane_view(&self) -> Uint32Array {
+        view_u32(&self.ref_lane)
+    }
+
+    /// Gets a view of the reference index column.
+    #[wasm_bindgen(js_name = refIndex)]
+    pub fn ref_index_view(&self) -> Uint32Array {
+        view_u32(&self.ref_index)
+    }
+
+    /// Gets a view of the block contents, `undefined` when not recorded.
+    #[wasm_bindgen(js_name = content)]
+    pub fn content_view(&self) -> Option<Uint8Array> {
+        // Safety: the view is only valid until the wasm memory grows, as
+        // documented on `pass_view`.
+        self.content
+            .as_ref()
+            .map(|content| unsafe { Uint8Array::view(content) })
+    }
+}
+
+impl TraceColumns {
+    /// Creates empty columns, which record the block contents when
+    /// `with_content` is set.
+    pub fn new(with_content: bool) -> TraceColumns {
+        TraceColumns {
+            content: if with_content { Some(Vec::new()) } else { None },
+            ..TraceColumns::default()
+        }
+    }
+
+    /// Creates the columns from the initial blocks and block fills of a
+    /// trace, with the block contents when `with_content` is set.
+    pub fn from_trace(trace: &Trace, with_content: bool) -> TraceColumns {
+        let mut columns = TraceColumns::new(with_content);
+        for event in trace.events.iter() {
+            columns.append(event);
+        }
+        columns
+    }
+
+    /// Reads the columns from their binary form.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TraceColumns> {
+        if bytes.len() < HEADER_LENGTH || bytes[0..4] != MAGIC {
+            return Err(Error::DecodingFail);
+        }
+        if read_u32(bytes, 4) != FORMAT_VERSION {
+            return Err(Error::IncorrectTraceVersion);
+        }
+        let rows = read_u32(bytes, 8) as u64;
+        let flags = read_u32(bytes, 12);
+        let content_length = if flags & FLAG_CONTENT != 0 {
+            rows * common::BLOCK_SIZE as u64
+        } else {
+            0
+        };
+        let length = HEADER_LENGTH as u64 + rows * COLUMNS as u64 * 4 + content_length;
+        if flags & !FLAG_CONTENT != 0 || bytes.len() as u64 != length {
+            return Err(Error::DecodingFail);
+        }
+        let rows = rows as usize;
+
+        let column = |n: usize| -> Vec<u32> {
+            let start = HEADER_LENGTH + n * rows * 4;
+            (0..rows)
+                .map(|row| read_u32(bytes, start + row * 4))
+                .collect()
+        };
+        let content_start = HEADER_LENGTH + rows * COLUMNS * 4;
+        Ok(TraceColumns {
+            pass: column(0),
+            lane: column(1),
+            slice: column(2),
+            index: column(3),
+            ref_lane: column(4),
+            ref_index: column(5),
+            content: if flags & FLAG_CONTENT != 0 {
+                Some(bytes[content_start..].to_vec())
+            } else {
+                None
+            },
+        })
+    }
+
+    fn columns(&self) -> [&Vec<u32>; COLUMNS] {
+        [
+            &self.pass,
+            &self.lane,
+            &self.slice,
+            &self.index,
+            &self.ref_lane,
+            &self.ref_index,
+        ]
+    }
+
+    /// Appends a row for an initial block or block fill, ignoring the other
+    /// events.
+    fn append(&mut self, event: &TraceEvent) {
+        match event {
+            TraceEvent::BlockInit(init) => self.push(
+                [0, init.lane, 0, init.index, NO_REFERENCE, NO_REFERENCE],
+                &init.content,
+            ),
+            TraceEvent::BlockFill(fill) => self.push(
+                [
+                    fill.pass,
+                    fill.lane,
+                    fill.slice,
+                    fill.index,
+                    fill.reference.lane,
+                    fill.reference.index,
+                ],
+                &fill.content,
+            ),
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, row: [u32; COLUMNS], content: &[u8]) {
+        self.pass.push(row[0]);
+        self.lane.push(row[1]);
+        self.slice.push(row[2]);
+        self.index.push(row[3]);
+        self.ref_lane.push(row[4]);
+        self.ref_index.push(row[5]);
+        if let Some(arena) = self.content.as_mut() {
+            arena.extend_from_slice(content);
+        }
+    }
+}
+
+/// Appends the rows of every batch, so a run can be recorded straight into
+/// the columns.
+impl TraceSink for TraceColumns {
+    fn write(&mut self, events: Vec<TraceEvent>) {
+        for event in events.iter() {
+            self.append(event);
+        }
+    }
+}
+
+/// The outcome of a run recorded into columns: the result, without trace
+/// events, and the columns.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnarHash {
+    /// The encoded hash, `None` for a partial run.
+    #[wasm_bindgen(skip)]
+    pub hash: Option<String>,
+
+    /// The result, holding the memory of a partial run.
+    #[wasm_bindgen(skip)]
+    pub result: Argon2Result,
+
+    /// The initial blocks and block fills of the run.
+    #[wasm_bindgen(skip)]
+    pub columns: TraceColumns,
+}
+
+#[wasm_bindgen]
+impl ColumnarHash {
+    /// Gets the encoded hash, `undefined` for a partial run.
+    pub fn hash(&self) -> Option<String> {
+        self.hash.clone()
+    }
+
+    /// Returns whether the run stopped early, leaving a partial result.
+    pub fn partial(&self) -> bool {
+        self.result.partial
+    }
+
+    /// Gets the result as JSON like `hash_encoded_js`, without trace events.
+    pub fn result_json(&self) -> String {
+        self.result.to_json()
+    }
+
+    /// Gets the columns.
+    pub fn columns(&self) -> TraceColumns {
+        self.columns.clone()
+    }
+}
+
+/// Hashes the password, recording the initial blocks and block fills
+/// straight into columns, with the block contents when `with_content` is
+/// set. Fails with `Error::IncorrectConfig` when the trace level is below
+/// `TraceLevel::Blocks`, which records no blocks.
+pub fn hash_encoded_columns(
+    pwd: &[u8],
+    salt: &[u8],
+    config: &Config,
+    with_content: bool,
+) -> Result<ColumnarHash> {
+    if !config.trace.records(TraceLevel::Blocks) {
+        return Err(Error::IncorrectConfig);
+    }
+    let mut result = Argon2Result::with_encoding(config.trace_encoding);
+    let mut columns = TraceColumns::new(with_content);
+    let hash = hash_encoded_with_sink(pwd, salt, config, &mut result, &mut columns)?;
+    Ok(ColumnarHash {
+        hash,
+        result,
+        columns,
+    })
+}
+
+/// Hashes the password like `hash_encoded_js`, recording the initial blocks
+/// and block fills straight into columns instead of a JSON result. The
+/// trace level must be `blocks` or above.
+#[wasm_bindgen]
+pub fn hash_encoded_columns_js(
+    pwd: String,
+    salt: String,
+    config_json: String,
+    with_content: bool,
+) -> JsResult<ColumnarHash> {
+    panic::set_hook(Box::new(console_error_panic_hook::hook));
+    let config = Config::from_json(config_json.as_str())?;
+    Ok(hash_encoded_columns(
+        pwd.as_bytes(),
+        salt.as_bytes(),
+        &config,
+        with_content,
+    )?)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn view_u32(column: &[u32]) -> Uint32Array {
+    // Safety: the view is only valid until the wasm memory grows, as
+    // documented on `pass_view`.
+    unsafe { Uint32Array::view(column) }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::{hash_encoded, hash_encoded_with_sink};
+    use crate::columnar::{
+        hash_encoded_columns, hash_encoded_columns_js, TraceColumns, FORMAT_VERSION, NO_REFERENCE,
+    };
+    use crate::config::Config;
+    use crate::error::Error;
+    use crate::result::Argon2Result;
+    use crate::trace::{TraceEvent, TraceLevel, TraceOptions};
+
+    fn columns(with_content: bool) -> (Argon2Result, TraceColumns) {
+        let config = Config {
+            lanes: 2,
+            mem_cost: 16,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+        let columns = TraceColumns::from_trace(&result.trace, with_content);
+        (result, columns)
+    }
+
+    #[test]
+    fn from_trace_has_a_row_per_written_block() {
+        let (result, columns) = columns(true);
+        assert_eq!(columns.len(), 2 * 16);
+        assert_eq!(columns.ref_lane[0..4], [NO_REFERENCE; 4]);
+        let fills = result.trace.events.iter().filter_map(|e| match e {
+            TraceEvent::BlockFill(fill) => Some(fill),
+            _ => None,
+        });
+        for (row, fill) in (4..).zip(fills) {
+            assert_eq!(columns.pass[row], fill.pass);
+            assert_eq!(columns.index[row], fill.index);
+            assert_eq!(columns.ref_index[row], fill.reference.index);
+            let content = &columns.content.as_ref().unwrap()[row * 1024..(row + 1) * 1024];
+            assert_eq!(content, &fill.content[..]);
+        }
+    }
+
+    #[test]
+    fn sink_records_the_rows_of_from_trace() {
+        for with_content in [false, true] {
+            let (result, expected) = columns(with_content);
+            let config = Config {
+                lanes: 2,
+                mem_cost: 16,
+                time_cost: 2,
+                trace: TraceOptions::new(TraceLevel::Blocks),
+                ..Config::default()
+            };
+            let mut columns = TraceColumns::new(with_content);
+            let mut state = Argon2Result::new();
+            hash_encoded_with_sink(b"password", b"somesalt", &config, &mut state, &mut columns)
+                .unwrap();
+            assert_eq!(columns, expected);
+            assert_eq!(state.hash, result.hash);
+        }
+    }
+
+    #[test]
+    fn hash_encoded_columns_js_returns_the_columns_and_hash() {
+        let config_json =
+            r#"{"memory": "16", "parallelism": "2", "iterations": "2", "trace_level": "blocks"}"#;
+        let run = hash_encoded_columns_js(
+            "password".to_string(),
+            "somesalt".to_string(),
+            config_json.to_string(),
+            true,
+        )
+        .unwrap();
+        let (result, columns) = columns(true);
+        assert_eq!(run.columns(), columns);
+        assert_eq!(run.hash(), Some(result.hash));
+        assert!(!run.partial());
+        assert!(run.result.trace.events.is_empty());
+    }
+
+    #[test]
+    fn hash_encoded_columns_keeps_the_memory_of_partial_runs() {
+        let config = Config {
+            lanes: 2,
+            mem_cost: 16,
+            time_cost: 2,
+            stop_at_iteration: Some(0),
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let run = hash_encoded_columns(b"password", b"somesalt", &config, false).unwrap();
+        assert_eq!(run.hash, None);
+        assert!(run.partial());
+        assert_eq!(run.result.memory.len(), 16);
+        assert_eq!(run.columns.len(), 16);
+    }
+
+    #[test]
+    fn hash_encoded_columns_rejects_levels_without_blocks() {
+        for level in [TraceLevel::Off, TraceLevel::References] {
+            let config = Config {
+                trace: TraceOptions {
+                    level,
+                    ..TraceOptions::default()
+                },
+                ..Config::default()
+            };
+            let res = hash_encoded_columns(b"password", b"somesalt", &config, false);
+            assert_eq!(res, Err(Error::IncorrectConfig));
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip_with_and_without_content() {
+        for with_content in [false, true] {
+            let (_, columns) = columns(with_content);
+            let bytes = columns.to_bytes();
+            let content = if with_content { 32 * 1024 } else { 0 };
+            assert_eq!(bytes.len(), 16 + 32 * 6 * 4 + content);
+            assert_eq!(TraceColumns::from_bytes(&bytes), Ok(columns));
+        }
+    }
+
+    #[test]
+    fn from_bytes_rejects_malformed_input() {
+        let (_, columns) = columns(false);
+        let bytes = columns.to_bytes();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(
+            TraceColumns::from_bytes(&wrong_magic),
+            Err(Error::DecodingFail)
+        );
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            TraceColumns::from_bytes(&wrong_version),
+            Err(Error::IncorrectTraceVersion)
+        );
+
+        assert_eq!(
+            TraceColumns::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::DecodingFail)
+        );
+    }
+}
@@ -6,13 +6,20 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::convert::TryFrom;
+use std::ops::Range;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
+use crate::bytes::ByteEncoding;
 use crate::common;
+use crate::error::Error;
+use crate::result::Result;
+use crate::snapshot::SnapshotMode;
 use crate::thread_mode::ThreadMode;
+use crate::trace::{TraceLevel, TraceOptions};
 use crate::variant::Variant;
 use crate::version::Version;
 
@@ -63,7 +70,18 @@ pub struct Config<'a> {
     /// The version number.
     pub version: Version,
 
-    pub stop_at_iteration: u32,
+    /// The pass after which hashing stops, leaving a partial result without
+    /// a final hash. `None` runs all passes.
+    pub stop_at_iteration: Option<u32>,
+
+    /// The encoding of byte payloads in the serialized trace.
+    pub trace_encoding: ByteEncoding,
+
+    /// The events recorded in the trace.
+    pub trace: TraceOptions,
+
+    /// What is stored of the memory at every sync point.
+    pub snapshot: SnapshotMode,
 }
 
 impl<'a> Config<'a> {
@@ -71,54 +89,40 @@ impl<'a> Config<'a> {
         self.thread_mode == ThreadMode::Sequential || self.lanes == 1
     }
 
-    pub fn from_json(config_json: &'a str) -> Config<'a> {
-        let raw_config: Value = serde_json::from_str(config_json).unwrap();
+    /// Attempts to create a config from its JSON form. Missing keys keep
+    /// their default values and numbers may be given as numbers or strings.
+    #[allow(clippy::field_reassign_with_default)]
+    pub fn from_json(config_json: &str) -> Result<Config<'a>> {
+        let raw_config: Value = serde_json::from_str(config_json)?;
         let mut config = Config::default();
-        config.hash_length = raw_config["hash_length"]
-            .as_str()
-            .unwrap_or(common::DEF_HASH_LENGTH.to_string().as_str())
-            .parse::<u32>()
-            .unwrap();
-        config.lanes = raw_config["parallelism"]
-            .as_str()
-            .unwrap_or(common::DEF_LANES.to_string().as_str())
-            .parse::<u32>()
-            .unwrap();
-        config.mem_cost = raw_config["memory"]
-            .as_str()
-            .unwrap_or(common::DEF_MEMORY.to_string().as_str())
-            .parse::<u32>()
-            .unwrap();
-        config.time_cost = raw_config["iterations"]
-            .as_str()
-            .unwrap_or(common::DEF_TIME.to_string().as_str())
-            .parse::<u32>()
-            .unwrap();
-        config.stop_at_iteration = raw_config["stop_at_iteration"]
-            .as_str()
-            .unwrap_or("0")
-            .parse::<u32>()
-            .unwrap();
+        config.hash_length = number(&raw_config["hash_length"], common::DEF_HASH_LENGTH)?;
+        config.lanes = number(&raw_config["parallelism"], common::DEF_LANES)?;
+        config.mem_cost = number(&raw_config["memory"], common::DEF_MEMORY)?;
+        config.time_cost = number(&raw_config["iterations"], common::DEF_TIME)?;
+        config.stop_at_iteration = match &raw_config["stop_at_iteration"] {
+            Value::Null => None,
+            stop => Some(number(stop, 0)?),
+        };
+        config.trace_encoding = parse(&raw_config["encoding"], ByteEncoding::default())?;
+        config.trace.level = parse(&raw_config["trace_level"], TraceLevel::default())?;
+        config.trace.pass = range(&raw_config["trace_pass"])?;
+        config.trace.lane = range(&raw_config["trace_lane"])?;
+        config.trace.slice = range(&raw_config["trace_slice"])?;
+        config.trace.index = range(&raw_config["trace_index"])?;
+        config.trace.every = number(&raw_config["trace_every"], 1)?;
+        if config.trace.every == 0 {
+            return Err(Error::IncorrectConfig);
+        }
+        config.snapshot = parse(&raw_config["snapshot"], SnapshotMode::default())?;
         //DEFAULTS
-        config.thread_mode = ThreadMode::from_str(
-            raw_config["thread_mode"]
-                .as_str()
-                .unwrap_or(ThreadMode::default().as_str()),
-        )
-        .unwrap();
-        config.variant = Variant::from_str(
-            raw_config["variant"]
-                .as_str()
-                .unwrap_or(Variant::default().as_uppercase_str()),
-        )
-        .unwrap();
-        config.version = Version::from_str(
-            raw_config["version"]
-                .as_str()
-                .unwrap_or(Version::default().as_str()),
-        )
-        .unwrap();
-        config
+        config.thread_mode = parse(&raw_config["thread_mode"], ThreadMode::default())?;
+        if let Some(variant) = string(&raw_config["variant"])? {
+            config.variant = Variant::from_str(variant).map_err(|_| Error::IncorrectType)?;
+        }
+        if let Some(version) = string(&raw_config["version"])? {
+            config.version = Version::from_str(version).map_err(|_| Error::IncorrectVersion)?;
+        }
+        Ok(config)
     }
 
     pub fn default_json() -> String {
@@ -130,6 +134,9 @@ impl<'a> Config<'a> {
             "thread_mode": ThreadMode::default().as_str(),
             "variant": Variant::default().as_uppercase_str(),
             "version": Version::default().as_str(),
+            "encoding": ByteEncoding::default().as_str(),
+            "trace_level": TraceLevel::default().as_str(),
+            "snapshot": SnapshotMode::default().as_str(),
         })
         .to_string()
     }
@@ -147,16 +154,64 @@ impl<'a> Default for Config<'a> {
             time_cost: common::DEF_TIME,
             variant: Variant::default(),
             version: Version::default(),
-            stop_at_iteration: 0,
+            stop_at_iteration: None,
+            trace_encoding: ByteEncoding::default(),
+            trace: TraceOptions::default(),
+            snapshot: SnapshotMode::default(),
         }
     }
 }
 
+/// Gets the string value of a key, `None` when the key is missing.
+fn string(value: &Value) -> Result<Option<&str>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s)),
+        _ => Err(Error::IncorrectConfig),
+    }
+}
+
+/// Parses a number given as a JSON number or string.
+fn number(value: &Value, default: u32) -> Result<u32> {
+    let number = match value {
+        Value::Null => return Ok(default),
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s.parse::<u32>().ok(),
+        _ => None,
+    };
+    number.ok_or(Error::IncorrectConfig)
+}
+
+/// Parses a value with `FromStr`.
+fn parse<T: FromStr>(value: &Value, default: T) -> Result<T> {
+    match string(value)? {
+        Some(s) => T::from_str(s).map_err(|_| Error::IncorrectConfig),
+        None => Ok(default),
+    }
+}
+
+/// Parses a range written as `start..end`, with `end` excluded.
+fn range(value: &Value) -> Result<Option<Range<u32>>> {
+    let range = match string(value)? {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+    let (start, end) = range.split_once("..").ok_or(Error::IncorrectConfig)?;
+    match (start.parse::<u32>(), end.parse::<u32>()) {
+        (Ok(start), Ok(end)) => Ok(Some(start..end)),
+        _ => Err(Error::IncorrectConfig),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
+    use crate::bytes::ByteEncoding;
     use crate::config::Config;
+    use crate::error::Error;
+    use crate::snapshot::SnapshotMode;
     use crate::thread_mode::ThreadMode;
+    use crate::trace::{TraceLevel, TraceOptions};
     use crate::variant::Variant;
     use crate::version::Version;
 
@@ -170,5 +225,78 @@ mod tests {
         assert_eq!(config.time_cost, 3);
         assert_eq!(config.variant, Variant::Argon2i);
         assert_eq!(config.version, Version::Version13);
+        assert_eq!(config.trace_encoding, ByteEncoding::Hex);
+    }
+
+    #[test]
+    fn from_json_parses_stop_at_iteration() {
+        let config = Config::from_json(r#"{"stop_at_iteration": "1"}"#).unwrap();
+        assert_eq!(config.stop_at_iteration, Some(1));
+        let config = Config::from_json("{}").unwrap();
+        assert_eq!(config.stop_at_iteration, None);
+    }
+
+    #[test]
+    fn from_json_parses_trace_encoding() {
+        let config = Config::from_json(r#"{"encoding": "base64"}"#).unwrap();
+        assert_eq!(config.trace_encoding, ByteEncoding::Base64);
+        let config = Config::from_json("{}").unwrap();
+        assert_eq!(config.trace_encoding, ByteEncoding::Hex);
+    }
+
+    #[test]
+    fn from_json_parses_snapshot_mode() {
+        let config = Config::from_json(r#"{"snapshot": "digest"}"#).unwrap();
+        assert_eq!(config.snapshot, SnapshotMode::Digest);
+        let config = Config::from_json("{}").unwrap();
+        assert_eq!(config.snapshot, SnapshotMode::Off);
+    }
+
+    #[test]
+    fn from_json_parses_trace_options() {
+        let config = Config::from_json(
+            r#"{"trace_level": "references", "trace_pass": "1..2", "trace_lane": "0..1",
+                "trace_slice": "2..4", "trace_index": "16..32", "trace_every": "8"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.trace,
+            TraceOptions {
+                level: TraceLevel::References,
+                pass: Some(1..2),
+                lane: Some(0..1),
+                slice: Some(2..4),
+                index: Some(16..32),
+                every: 8,
+            }
+        );
+        let config = Config::from_json("{}").unwrap();
+        assert_eq!(config.trace, TraceOptions::default());
+    }
+
+    #[test]
+    fn from_json_accepts_numbers() {
+        let config = Config::from_json(r#"{"memory": 32, "iterations": "2"}"#).unwrap();
+        assert_eq!(config.mem_cost, 32);
+        assert_eq!(config.time_cost, 2);
+    }
+
+    #[test]
+    fn from_json_with_incorrect_values_returns_correct_error() {
+        let cases = [
+            ("{", Error::DecodingFail),
+            (r#"{"memory": "lots"}"#, Error::IncorrectConfig),
+            (r#"{"memory": -1}"#, Error::IncorrectConfig),
+            (r#"{"parallelism": 4294967296}"#, Error::IncorrectConfig),
+            (r#"{"trace_pass": "1-2"}"#, Error::IncorrectConfig),
+            (r#"{"trace_every": "0"}"#, Error::IncorrectConfig),
+            (r#"{"trace_level": "everything"}"#, Error::IncorrectConfig),
+            (r#"{"thread_mode": 2}"#, Error::IncorrectConfig),
+            (r#"{"variant": "Argon3"}"#, Error::IncorrectType),
+            (r#"{"version": "12"}"#, Error::IncorrectVersion),
+        ];
+        for (json, error) in cases.iter() {
+            assert_eq!(Config::from_json(json), Err(error.clone()), "{}", json);
+        }
     }
 }
@@ -0,0 +1,808 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::block::Block;
+use crate::common;
+use crate::config::Config;
+use crate::context::Context;
+use crate::core::{self, Position};
+use crate::memory::Memory;
+use crate::result::Result;
+use crate::trace::{
+    AddressBlock, BlockCoord, BlockFill, BlockInit, Compression, Finalization, Hprime, Prehash,
+    ReferenceSelection, Trace, TraceEvent,
+};
+
+/// Inconsistency between a recorded trace event and the value recomputed
+/// from its recorded inputs. `event` is the position of the event in the
+/// trace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Mismatch {
+    /// The recorded H0 fields or digest differ from the ones derived from the
+    /// context.
+    Prehash { event: usize },
+
+    /// The recorded seed is not the little-endian block index and lane.
+    Seed { event: usize, lane: u32, index: u32 },
+
+    /// The V_i, parts or output of H' differ from the ones recomputed from
+    /// the recorded input and length, or the length is not the one of an
+    /// initial block or the tag.
+    Hprime { event: usize },
+
+    /// The content of an initial block differs from H'(H0 || seed).
+    BlockInit { event: usize, lane: u32, index: u32 },
+
+    /// The addresses differ from the ones generated from the input fields,
+    /// or the input fields do not match the context.
+    AddressBlock {
+        event: usize,
+        pass: u32,
+        lane: u32,
+        slice: u32,
+        counter: u32,
+    },
+
+    /// The intermediate values or the reference block of a reference
+    /// selection differ from the ones derived from its J1 and J2, J1 and J2
+    /// are not the first word of the previous block with data-dependent
+    /// addressing, or the reference differs from the one used by the
+    /// following block fill.
+    Reference { event: usize, lane: u32, index: u32 },
+
+    /// The previous block is not the block preceding the filled block.
+    Previous {
+        event: usize,
+        pass: u32,
+        lane: u32,
+        index: u32,
+    },
+
+    /// The recorded intermediate values of G differ from the recomputed ones.
+    Compression {
+        event: usize,
+        pass: u32,
+        lane: u32,
+        index: u32,
+    },
+
+    /// The content of a filled block differs from G(prev, ref).
+    BlockFill {
+        event: usize,
+        pass: u32,
+        lane: u32,
+        index: u32,
+    },
+
+    /// The last blocks, their accumulated XOR or the tag differ from the
+    /// last column and H' of its XOR.
+    Finalization { event: usize },
+
+    /// A block coordinate of the event lies outside the memory of the
+    /// context, or an initial block is not one of the first two of its lane.
+    Coordinates { event: usize },
+}
+
+/// Checks that a trace recorded while hashing the password with the supplied
+/// configuration is self-consistent.
+///
+/// The trace must be recorded at `TraceLevel::Blocks` or above without
+/// filters, as every block is recomputed from the recorded blocks.
+///
+/// # Examples
+///
+/// ```
+/// use argon2::{self, Argon2Result, Config};
+///
+/// let pwd = b"password";
+/// let salt = b"somesalt";
+/// let config = Config::default();
+/// let mut result = Argon2Result::new();
+/// argon2::hash_encoded(pwd, salt, &config, &mut result);
+/// let mismatches = argon2::check_trace(pwd, salt, &config, &result.trace).unwrap();
+/// assert!(mismatches.is_empty());
+/// ```
+pub fn check_trace(
+    pwd: &[u8],
+    salt: &[u8],
+    config: &Config,
+    trace: &Trace,
+) -> Result<Vec<Mismatch>> {
+    let context = Context::new(config.clone(), pwd, salt)?;
+    Ok(check(&context, trace))
+}
+
+/// Recomputes every block and the tag in the trace from the inputs recorded
+/// before it and returns the mismatches found.
+///
+/// The recomputed blocks are written into a shadow memory as the trace is
+/// walked, so a single wrong entry is reported once instead of spreading to
+/// every block that depends on it.
+pub fn check<'a>(context: &'a Context<'a>, trace: &'a Trace) -> Vec<Mismatch> {
+    let mut checker = Checker {
+        context,
+        memory: Memory::new(context.config.lanes, context.lane_length),
+        h0: core::h0(context),
+        pending_reference: None,
+        pending_compression: None,
+        mismatches: Vec::new(),
+    };
+    for (event, entry) in trace.events.iter().enumerate() {
+        match entry {
+            TraceEvent::Prehash(prehash) => checker.check_prehash(event, prehash),
+            TraceEvent::Hprime(hprime) => checker.check_hprime(event, hprime),
+            TraceEvent::BlockInit(init) => checker.check_init(event, init),
+            TraceEvent::AddressBlock(addresses) => checker.check_addresses(event, addresses),
+            TraceEvent::ReferenceSelection(selection) => {
+                checker.pending_reference = Some((
+                    event,
+                    BlockCoord {
+                        lane: selection.ref_lane,
+                        index: selection.ref_index,
+                    },
+                    checker.check_selection(selection),
+                ));
+            }
+            TraceEvent::Compression(compression) => {
+                checker.pending_compression = Some((event, compression));
+            }
+            TraceEvent::BlockFill(fill) => checker.check_fill(event, fill),
+            TraceEvent::Finalization(finalization) => checker.check_final(event, finalization),
+        }
+    }
+    checker.mismatches
+}
+
+struct Checker<'a> {
+    context: &'a Context<'a>,
+    memory: Memory,
+    h0: [u8; common::PREHASH_SEED_LENGTH],
+    pending_reference: Option<(usize, BlockCoord, bool)>,
+    pending_compression: Option<(usize, &'a Compression)>,
+    mismatches: Vec<Mismatch>,
+}
+
+impl<'a> Checker<'a> {
+    fn check_prehash(&mut self, event: usize, prehash: &Prehash) {
+        if prehash.fields != core::h0_fields(self.context)
+            || *prehash.digest != self.h0[0..common::PREHASH_DIGEST_LENGTH]
+        {
+            self.mismatches.push(Mismatch::Prehash { event });
+        }
+    }
+
+    fn check_hprime(&mut self, event: usize, hprime: &Hprime) {
+        // H' derives the initial blocks from H0 || seed and the tag from the
+        // XOR of the last blocks; any other length is not computed by the run.
+        let length = match hprime.input.len() {
+            common::PREHASH_SEED_LENGTH => common::BLOCK_SIZE as u32,
+            common::BLOCK_SIZE => self.context.config.hash_length,
+            _ => 0,
+        };
+        let mut expected = Hprime::default();
+        if length > 0 && hprime.length == length {
+            let mut out = vec![0u8; length as usize];
+            core::expand(&mut out, &hprime.input, Some(&mut expected));
+        }
+        if length == 0 || *hprime != expected {
+            self.mismatches.push(Mismatch::Hprime { event });
+        }
+    }
+
+    /// Returns whether the block lies inside the memory.
+    fn contains(&self, coord: &BlockCoord) -> bool {
+        coord.lane < self.context.config.lanes && coord.index < self.context.lane_length
+    }
+
+    fn check_init(&mut self, event: usize, init: &BlockInit) {
+        let (lane, index) = (init.lane, init.index);
+        if lane >= self.context.config.lanes || index >= 2 {
+            self.mismatches.push(Mismatch::Coordinates { event });
+            return;
+        }
+        if *init.h0 != self.h0[0..common::PREHASH_DIGEST_LENGTH] {
+            self.mismatches.push(Mismatch::Prehash { event });
+        }
+        let mut seed = [0u8; 8];
+        seed[0..4].copy_from_slice(&u32::to_le_bytes(index));
+        seed[4..8].copy_from_slice(&u32::to_le_bytes(lane));
+        if *init.seed != seed {
+            self.mismatches.push(Mismatch::Seed { event, lane, index });
+        }
+
+        let input = [&init.h0[..], &init.seed[..]].concat();
+        let mut expected = Block::zero();
+        core::hprime(expected.as_u8_mut(), &input);
+        if *init.content != *expected.as_u8() {
+            self.mismatches
+                .push(Mismatch::BlockInit { event, lane, index });
+        }
+        self.memory[(lane, index)] = expected;
+    }
+
+    fn check_addresses(&mut self, event: usize, addresses: &AddressBlock) {
+        let config = &self.context.config;
+        let mut input_block = Block::zero();
+        input_block[0] = addresses.pass as u64;
+        input_block[1] = addresses.lane as u64;
+        input_block[2] = addresses.slice as u64;
+        input_block[3] = addresses.memory_blocks as u64;
+        input_block[4] = addresses.time_cost as u64;
+        input_block[5] = addresses.variant as u64;
+        input_block[6] = addresses.counter.wrapping_sub(1) as u64;
+        let mut expected = Block::zero();
+        core::next_addresses(&mut expected, &mut input_block, &Block::zero());
+
+        let matches = addresses.memory_blocks == self.context.memory_blocks
+            && addresses.time_cost == config.time_cost
+            && addresses.variant == config.variant.as_u32()
+            && addresses.addresses.len() == common::ADDRESSES_IN_BLOCK as usize
+            && addresses
+                .addresses
+                .iter()
+                .enumerate()
+                .all(|(i, &[j1, j2])| expected[i] == (j1 as u64) | ((j2 as u64) << 32));
+        if !matches {
+            self.mismatches.push(Mismatch::AddressBlock {
+                event,
+                pass: addresses.pass,
+                lane: addresses.lane,
+                slice: addresses.slice,
+                counter: addresses.counter,
+            });
+        }
+    }
+
+    /// Returns whether the selection is the one derived from its J1 and J2,
+    /// and J1 and J2 are the first word of the previous block when the
+    /// addressing is data-dependent.
+    fn check_selection(&self, selection: &ReferenceSelection) -> bool {
+        let segment_length = self.context.segment_length;
+        let first = if selection.pass == 0 && selection.slice == 0 {
+            2
+        } else {
+            0
+        };
+        if selection.slice >= common::SYNC_POINTS || selection.lane >= self.context.config.lanes {
+            return false;
+        }
+        let index = match selection
+            .index
+            .checked_sub(selection.slice * segment_length)
+        {
+            Some(index) if index >= first && index < segment_length => index,
+            _ => return false,
+        };
+        let position = Position {
+            pass: selection.pass,
+            lane: selection.lane,
+            slice: selection.slice,
+            index,
+        };
+        let pseudo_rand = (selection.j1 as u64) | ((selection.j2 as u64) << 32);
+        if !core::data_independent_addressing(self.context, selection.pass, selection.slice) {
+            let lane_length = self.context.lane_length;
+            let prev = (selection.index + lane_length - 1) % lane_length;
+            if self.memory[(selection.lane, prev)][0] != pseudo_rand {
+                return false;
+            }
+        }
+        core::select_reference(self.context, &position, pseudo_rand) == *selection
+    }
+
+    fn check_fill(&mut self, event: usize, fill: &BlockFill) {
+        let (pass, lane, index) = (fill.pass, fill.lane, fill.index);
+        let block = BlockCoord { lane, index };
+        if ![block, fill.prev, fill.reference]
+            .iter()
+            .all(|coord| self.contains(coord))
+        {
+            self.pending_reference = None;
+            self.pending_compression = None;
+            self.mismatches.push(Mismatch::Coordinates { event });
+            return;
+        }
+        if let Some((selection, reference, valid)) = self.pending_reference.take() {
+            if !valid || reference != fill.reference {
+                self.mismatches.push(Mismatch::Reference {
+                    event: selection,
+                    lane,
+                    index,
+                });
+            }
+        }
+        let lane_length = self.context.lane_length;
+        let prev = BlockCoord {
+            lane,
+            index: (index + lane_length - 1) % lane_length,
+        };
+        if fill.prev != prev {
+            self.mismatches.push(Mismatch::Previous {
+                event,
+                pass,
+                lane,
+                index,
+            });
+        }
+
+        let mut expected = self.memory[(lane, index)].clone();
+        let pending_compression = self.pending_compression.take();
+        let mut internals = pending_compression.map(|(_, recorded)| Compression {
+            pass,
+            lane,
+            slice: fill.slice,
+            index,
+            rounds: recorded.rounds.as_ref().map(|_| Vec::new()),
+            ..Compression::default()
+        });
+        core::compress(
+            &self.memory[(fill.prev.lane, fill.prev.index)],
+            &self.memory[(fill.reference.lane, fill.reference.index)],
+            &mut expected,
+            fill.with_xor,
+            internals.as_mut(),
+        );
+        if let (Some((event, recorded)), Some(internals)) = (pending_compression, internals) {
+            if *recorded != internals {
+                self.mismatches.push(Mismatch::Compression {
+                    event,
+                    pass,
+                    lane,
+                    index,
+                });
+            }
+        }
+        if *fill.content != *expected.as_u8() {
+            self.mismatches.push(Mismatch::BlockFill {
+                event,
+                pass,
+                lane,
+                index,
+            });
+        }
+        self.memory[(lane, index)] = expected;
+    }
+
+    fn check_final(&mut self, event: usize, finalization: &Finalization) {
+        let (blockhash, blocks) = core::last_blocks(self.context, &self.memory);
+        let mut tag = vec![0u8; self.context.config.hash_length as usize];
+        core::hprime(&mut tag, blockhash.as_u8());
+        let expected = Finalization {
+            blocks,
+            tag: tag.into(),
+        };
+        if *finalization != expected {
+            self.mismatches.push(Mismatch::Finalization { event });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::hash_encoded;
+    use crate::config::Config;
+    use crate::consistency::{check, check_trace, Mismatch};
+    use crate::context::Context;
+    use crate::core::{self, Position};
+    use crate::result::Argon2Result;
+    use crate::trace::{BlockCoord, TraceEvent, TraceLevel, TraceOptions};
+    use crate::variant::Variant;
+    use crate::version::Version;
+
+    const PWD: &[u8] = b"password";
+    const SALT: &[u8] = b"somesalt";
+
+    fn traced(config: &Config) -> Argon2Result {
+        let mut result = Argon2Result::new();
+        hash_encoded(PWD, SALT, config, &mut result).unwrap();
+        result
+    }
+
+    fn configs() -> Vec<Config<'static>> {
+        let mut configs = Vec::new();
+        for variant in [Variant::Argon2d, Variant::Argon2i, Variant::Argon2id] {
+            for version in [Version::Version10, Version::Version13] {
+                for (lanes, mem_cost, time_cost) in [(1, 8, 1), (1, 64, 3), (4, 64, 2)] {
+                    configs.push(Config {
+                        variant,
+                        version,
+                        lanes,
+                        mem_cost,
+                        time_cost,
+                        secret: b"secret",
+                        ad: b"ad",
+                        hash_length: 80,
+                        ..Config::default()
+                    });
+                }
+            }
+        }
+        configs
+    }
+
+    #[test]
+    fn check_returns_no_mismatches_for_recorded_traces() {
+        for config in configs() {
+            let result = traced(&config);
+            let context = Context::new(config.clone(), PWD, SALT).unwrap();
+            assert_eq!(check(&context, &result.trace), vec![], "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn check_returns_no_mismatches_for_compression_traces() {
+        for trace_level in [TraceLevel::Compression, TraceLevel::QuarterRounds] {
+            for version in [Version::Version10, Version::Version13] {
+                let config = Config {
+                    version,
+                    lanes: 2,
+                    mem_cost: 16,
+                    time_cost: 2,
+                    trace: TraceOptions {
+                        level: trace_level,
+                        ..TraceOptions::default()
+                    },
+                    ..Config::default()
+                };
+                let result = traced(&config);
+                let context = Context::new(config, PWD, SALT).unwrap();
+                assert_eq!(check(&context, &result.trace), vec![]);
+            }
+        }
+    }
+
+    #[test]
+    fn check_detects_wrong_quarter_round() {
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 1,
+            trace: TraceOptions {
+                level: TraceLevel::QuarterRounds,
+                ..TraceOptions::default()
+            },
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let event = result
+            .trace
+            .events
+            .iter()
+            .position(|e| matches!(e, TraceEvent::Compression(_)))
+            .unwrap();
+        if let TraceEvent::Compression(compression) = &mut result.trace.events[event] {
+            compression.rounds.as_mut().unwrap()[100].output.0[3] ^= 0x10;
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::Compression {
+                event,
+                pass: 0,
+                lane: 0,
+                index: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn check_detects_wrong_address() {
+        let config = Config {
+            variant: Variant::Argon2i,
+            mem_cost: 16,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let event = result
+            .trace
+            .events
+            .iter()
+            .position(|e| matches!(e, TraceEvent::AddressBlock(_)))
+            .unwrap();
+        if let TraceEvent::AddressBlock(block) = &mut result.trace.events[event] {
+            block.addresses[5][1] ^= 1;
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::AddressBlock {
+                event,
+                pass: 0,
+                lane: 0,
+                slice: 0,
+                counter: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn check_detects_wrong_relative_position() {
+        let config = Config {
+            lanes: 2,
+            mem_cost: 16,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let (event, lane, index) = result
+            .trace
+            .events
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| match e {
+                TraceEvent::ReferenceSelection(s) if s.pass == 1 => Some((i, s.lane, s.index)),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        if let TraceEvent::ReferenceSelection(selection) = &mut result.trace.events[event] {
+            selection.relative_position += 1;
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::Reference { event, lane, index }]
+        );
+    }
+
+    #[test]
+    fn check_detects_wrong_prehash_field() {
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        if let TraceEvent::Prehash(prehash) = &mut result.trace.events[0] {
+            prehash.fields[1].bytes.0[0] ^= 1;
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::Prehash { event: 0 }]
+        );
+    }
+
+    #[test]
+    fn check_detects_wrong_hprime_part() {
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        if let TraceEvent::Hprime(hprime) = &mut result.trace.events[1] {
+            hprime.parts[3].offset += 1;
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::Hprime { event: 1 }]
+        );
+    }
+
+    #[test]
+    fn check_detects_wrong_hprime_length() {
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let tag = result
+            .trace
+            .events
+            .iter()
+            .rposition(|e| matches!(e, TraceEvent::Hprime(_)))
+            .unwrap();
+        for (event, length) in [(1, 1023), (tag, u32::MAX)] {
+            if let TraceEvent::Hprime(hprime) = &mut result.trace.events[event] {
+                hprime.length = length;
+            }
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        let mismatches = check(&context, &result.trace);
+        assert!(mismatches.contains(&Mismatch::Hprime { event: 1 }));
+        assert!(mismatches.contains(&Mismatch::Hprime { event: tag }));
+    }
+
+    #[test]
+    fn check_detects_wrong_accumulator() {
+        let config = Config {
+            lanes: 2,
+            mem_cost: 16,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let event = result.trace.events.len() - 1;
+        if let TraceEvent::Finalization(finalization) = &mut result.trace.events[event] {
+            finalization.blocks[1].accumulator.0[0] ^= 1;
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::Finalization { event }]
+        );
+    }
+
+    #[test]
+    fn check_trace_validates_config() {
+        let config = Config {
+            time_cost: 0,
+            ..Config::default()
+        };
+        let result = check_trace(PWD, SALT, &config, &Default::default());
+        assert_eq!(result, Err(crate::error::Error::TimeTooSmall));
+    }
+
+    #[test]
+    fn check_detects_wrong_initial_block_content() {
+        let config = Config {
+            mem_cost: 16,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let (first, second) = match &result.trace.events[1..5] {
+            [_, TraceEvent::BlockInit(a), _, TraceEvent::BlockInit(b)] => (a.clone(), b.clone()),
+            _ => panic!("trace does not start with the prehash and two initial blocks"),
+        };
+        let mut wrong = second;
+        wrong.content = first.content;
+        result.trace.events[4] = TraceEvent::BlockInit(wrong);
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::BlockInit {
+                event: 4,
+                lane: 0,
+                index: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn check_detects_wrong_filled_block() {
+        let config = Config {
+            mem_cost: 16,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let last_fill = result
+            .trace
+            .events
+            .iter()
+            .rposition(|e| matches!(e, TraceEvent::BlockFill(_)))
+            .unwrap();
+        if let TraceEvent::BlockFill(fill) = &mut result.trace.events[last_fill] {
+            fill.content.0[0] ^= 1;
+        }
+
+        let context = Context::new(config, PWD, SALT).unwrap();
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::BlockFill {
+                event: last_fill,
+                pass: 0,
+                lane: 0,
+                index: 15
+            }]
+        );
+    }
+
+    #[test]
+    fn check_detects_coordinates_outside_the_memory() {
+        let config = Config {
+            lanes: 2,
+            mem_cost: 16,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let result = traced(&config);
+        let fill = result
+            .trace
+            .events
+            .iter()
+            .position(|e| matches!(e, TraceEvent::BlockFill(_)))
+            .unwrap();
+        let tampers: [fn(&mut TraceEvent); 4] = [
+            |e| match e {
+                TraceEvent::BlockInit(init) => init.lane = 2,
+                _ => unreachable!(),
+            },
+            |e| match e {
+                TraceEvent::BlockFill(fill) => fill.index = 8,
+                _ => unreachable!(),
+            },
+            |e| match e {
+                TraceEvent::BlockFill(fill) => fill.prev.lane = u32::MAX,
+                _ => unreachable!(),
+            },
+            |e| match e {
+                TraceEvent::BlockFill(fill) => {
+                    fill.reference = BlockCoord {
+                        lane: 0,
+                        index: 100,
+                    }
+                }
+                _ => unreachable!(),
+            },
+        ];
+        for (i, tamper) in tampers.iter().enumerate() {
+            let event = if i == 0 { 2 } else { fill };
+            let mut trace = result.trace.clone();
+            tamper(&mut trace.events[event]);
+
+            let mismatches = check_trace(PWD, SALT, &config, &trace).unwrap();
+            assert_eq!(mismatches[0], Mismatch::Coordinates { event });
+        }
+    }
+
+    #[test]
+    fn check_detects_data_dependent_value_not_taken_from_previous_block() {
+        let config = Config {
+            variant: Variant::Argon2d,
+            mem_cost: 64,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = traced(&config);
+        let context = Context::new(config.clone(), PWD, SALT).unwrap();
+        // Find a selection whose reference stays the same when J1 changes,
+        // so only the source of J1 reveals the change.
+        let (event, selection) = result
+            .trace
+            .events
+            .iter()
+            .enumerate()
+            .find_map(|(i, e)| match e {
+                TraceEvent::ReferenceSelection(s) => {
+                    let position = Position {
+                        pass: s.pass,
+                        lane: s.lane,
+                        slice: s.slice,
+                        index: s.index - s.slice * context.segment_length,
+                    };
+                    let pseudo_rand = ((s.j1 ^ 1) as u64) | ((s.j2 as u64) << 32);
+                    let tampered = core::select_reference(&context, &position, pseudo_rand);
+                    let same = tampered.ref_lane == s.ref_lane && tampered.ref_index == s.ref_index;
+                    Some((i, tampered)).filter(|_| same)
+                }
+                _ => None,
+            })
+            .unwrap();
+        let (lane, index) = (selection.lane, selection.index);
+        result.trace.events[event] = TraceEvent::ReferenceSelection(selection);
+
+        assert_eq!(
+            check(&context, &result.trace),
+            vec![Mismatch::Reference { event, lane, index }]
+        );
+    }
+}
@@ -36,21 +36,10 @@ pub struct Context<'a> {
 
 impl<'a> Context<'a> {
     /// Attempts to create a new context.
+    #[allow(clippy::absurd_extreme_comparisons)]
     pub fn new(config: Config<'a>, pwd: &'a [u8], salt: &'a [u8]) -> Result<Context<'a>> {
-        if config.lanes < common::MIN_LANES {
-            return Err(Error::LanesTooFew);
-        } else if config.lanes > common::MAX_LANES {
-            return Err(Error::LanesTooMany);
-        }
-
+        check_layout(&config)?;
         let lanes = config.lanes;
-        if config.mem_cost < common::MIN_MEMORY {
-            return Err(Error::MemoryTooLittle);
-        } else if config.mem_cost > common::MAX_MEMORY {
-            return Err(Error::MemoryTooMuch);
-        } else if config.mem_cost < 8 * lanes {
-            return Err(Error::MemoryTooLittle);
-        }
 
         if config.time_cost < common::MIN_TIME {
             return Err(Error::TimeTooSmall);
@@ -58,6 +47,12 @@ impl<'a> Context<'a> {
             return Err(Error::TimeTooLarge);
         }
 
+        if let Some(stop_at_iteration) = config.stop_at_iteration {
+            if stop_at_iteration >= config.time_cost {
+                return Err(Error::StopAtIterationTooLarge);
+            }
+        }
+
         let pwd_len = pwd.len();
         if pwd_len < common::MIN_PWD_LENGTH as usize {
             return Err(Error::PwdTooShort);
@@ -92,12 +87,7 @@ impl<'a> Context<'a> {
             return Err(Error::OutputTooLong);
         }
 
-        let mut memory_blocks = config.mem_cost;
-        if memory_blocks < 2 * common::SYNC_POINTS * lanes {
-            memory_blocks = 2 * common::SYNC_POINTS * lanes;
-        }
-
-        let segment_length = memory_blocks / (lanes * common::SYNC_POINTS);
+        let segment_length = segment_length(&config);
         let memory_blocks = segment_length * (lanes * common::SYNC_POINTS);
         let lane_length = segment_length * common::SYNC_POINTS;
         Ok(Context {
@@ -111,13 +101,46 @@ impl<'a> Context<'a> {
     }
 }
 
+/// Checks the lanes and memory cost of a config, which determine the layout
+/// of the memory.
+#[allow(clippy::absurd_extreme_comparisons)]
+pub fn check_layout(config: &Config) -> Result<()> {
+    if config.lanes < common::MIN_LANES {
+        return Err(Error::LanesTooFew);
+    } else if config.lanes > common::MAX_LANES {
+        return Err(Error::LanesTooMany);
+    }
+
+    if config.mem_cost < common::MIN_MEMORY {
+        return Err(Error::MemoryTooLittle);
+    } else if config.mem_cost > common::MAX_MEMORY {
+        return Err(Error::MemoryTooMuch);
+    } else if config.mem_cost < 8 * config.lanes {
+        return Err(Error::MemoryTooLittle);
+    }
+    Ok(())
+}
+
+/// Gets the length of a segment for the memory cost and lanes of a config.
+pub fn segment_length(config: &Config) -> u32 {
+    let lanes = config.lanes;
+    let mut memory_blocks = config.mem_cost;
+    if memory_blocks < 2 * common::SYNC_POINTS * lanes {
+        memory_blocks = 2 * common::SYNC_POINTS * lanes;
+    }
+    memory_blocks / (lanes * common::SYNC_POINTS)
+}
+
 #[cfg(test)]
 mod tests {
 
+    use crate::bytes::ByteEncoding;
     use crate::config::Config;
     use crate::context::Context;
     use crate::error::Error;
+    use crate::snapshot::SnapshotMode;
     use crate::thread_mode::ThreadMode;
+    use crate::trace::TraceOptions;
     use crate::variant::Variant;
     use crate::version::Version;
 
@@ -133,7 +156,10 @@ mod tests {
             time_cost: 3,
             variant: Variant::Argon2i,
             version: Version::Version13,
-            stop_at_iteration: 0,
+            stop_at_iteration: None,
+            trace_encoding: ByteEncoding::default(),
+            trace: TraceOptions::default(),
+            snapshot: SnapshotMode::Off,
         };
         let pwd = b"password";
         let salt = b"somesalt";
@@ -186,6 +212,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn new_with_too_large_stop_at_iteration_returns_correct_error() {
+        let config = Config {
+            time_cost: 3,
+            stop_at_iteration: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            Context::new(config, &[0u8; 8], &[0u8; 8]),
+            Err(Error::StopAtIterationTooLarge)
+        );
+    }
+
     #[test]
     fn new_with_too_few_lanes_returns_correct_error() {
         let config = Config {
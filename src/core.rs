@@ -10,39 +10,74 @@ use crate::block::Block;
 use crate::common;
 use crate::context::Context;
 use crate::memory::Memory;
-use crate::result::{Argon2Result, Argon2State, Argon2Value, Argon2ValueBuilder};
+use crate::progress::{Hooks, Progress};
+use crate::result::{Argon2Result, Result};
+use crate::snapshot;
+use crate::trace::{
+    AddressBlock, BlockCoord, BlockFill, BlockInit, Compression, Finalization, Hprime, HprimePart,
+    LastBlock, Prehash, PrehashField, QuarterRound, ReferenceSelection, TraceEvent, TraceLevel,
+};
 use crate::variant::Variant;
 use crate::version::Version;
 use blake2b_simd::Params;
 #[cfg(feature = "crossbeam-utils")]
 use crossbeam_utils::thread::scope;
-use serde::__private::from_utf8_lossy;
 
 /// Position of the block currently being operated on.
-#[derive(Clone, Debug)]
-struct Position {
-    pass: u32,
-    lane: u32,
-    slice: u32,
-    index: u32,
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// The pass.
+    pub pass: u32,
+
+    /// The lane.
+    pub lane: u32,
+
+    /// The slice.
+    pub slice: u32,
+
+    /// The index of the block within the segment.
+    pub index: u32,
 }
 
 /// Initializes the memory.
 pub fn initialize(context: &Context, memory: &mut Memory, state: &mut Argon2Result) {
-    fill_first_blocks(context, memory, &mut h0(context), state);
+    let mut h0 = h0(context);
+    if context.config.trace.records(TraceLevel::Blocks) {
+        state.record(TraceEvent::Prehash(Prehash {
+            fields: h0_fields(context),
+            digest: h0[0..common::PREHASH_DIGEST_LENGTH].into(),
+        }));
+    }
+    fill_first_blocks(context, memory, &mut h0, state);
 }
 
-/// Fills all the memory blocks.
-pub fn fill_memory_blocks(context: &Context, memory: &mut Memory, state: &mut Argon2Result) {
+/// Fills all the memory blocks, handing the events recorded for every
+/// segment (every slice when filling lanes in parallel) to the sink of the
+/// hooks and reporting the progress after it. Stops with `Error::Cancelled`
+/// when the cancellation token is set before a segment (a slice) starts.
+pub fn fill_memory_blocks(
+    context: &Context,
+    memory: &mut Memory,
+    state: &mut Argon2Result,
+    hooks: &mut Hooks,
+) -> Result<()> {
     if context.config.uses_sequential() {
-        fill_memory_blocks_st(context, memory, state);
+        fill_memory_blocks_st(context, memory, state, hooks)
     } else {
-        fill_memory_blocks_mt(context, memory);
+        fill_memory_blocks_mt(context, memory, state, hooks)
+    }
+}
+
+/// Gets the number of passes to run, honouring `stop_at_iteration`.
+pub fn passes(context: &Context) -> u32 {
+    match context.config.stop_at_iteration {
+        Some(stop_at_iteration) => stop_at_iteration + 1,
+        None => context.config.time_cost,
     }
 }
 
 /// Calculates the final hash and returns it.
-pub fn finalize(context: &Context, memory: &Memory) -> Vec<u8> {
+pub fn finalize(context: &Context, memory: &Memory, state: &mut Argon2Result) -> Vec<u8> {
     let mut blockhash = memory[context.lane_length - 1].clone();
     for l in 1..context.config.lanes {
         let last_block_in_lane = l * context.lane_length + (context.lane_length - 1);
@@ -50,10 +85,38 @@ pub fn finalize(context: &Context, memory: &Memory) -> Vec<u8> {
     }
 
     let mut hash = vec![0u8; context.config.hash_length as usize];
-    hprime(hash.as_mut_slice(), blockhash.as_u8());
+    if context.config.trace.records(TraceLevel::Blocks) {
+        let mut internals = Hprime::default();
+        expand(hash.as_mut_slice(), blockhash.as_u8(), Some(&mut internals));
+        state.record(TraceEvent::Hprime(internals));
+        state.record(TraceEvent::Finalization(Finalization {
+            blocks: last_blocks(context, memory).1,
+            tag: hash.clone().into(),
+        }));
+    } else {
+        hprime(hash.as_mut_slice(), blockhash.as_u8());
+    }
     hash
 }
 
+/// XORs the last block of every lane, returning the result and every block
+/// with the accumulated value after it.
+pub fn last_blocks(context: &Context, memory: &Memory) -> (Block, Vec<LastBlock>) {
+    let index = context.lane_length - 1;
+    let mut blockhash = Block::zero();
+    let mut blocks = Vec::with_capacity(context.config.lanes as usize);
+    for lane in 0..context.config.lanes {
+        let last_block_in_lane = &memory[(lane, index)];
+        blockhash ^= last_block_in_lane;
+        blocks.push(LastBlock {
+            block: BlockCoord { lane, index },
+            content: last_block_in_lane.as_u8().into(),
+            accumulator: blockhash.as_u8().into(),
+        });
+    }
+    (blockhash, blocks)
+}
+
 fn blake2b(out: &mut [u8], input: &[&[u8]]) {
     let mut blake = Params::new().hash_length(out.len()).to_state();
     for slice in input {
@@ -68,10 +131,27 @@ fn f_bla_mka(x: u64, y: u64) -> u64 {
     x.wrapping_add(y.wrapping_add(xy.wrapping_add(xy)))
 }
 
-fn fill_block(prev_block: &Block, ref_block: &Block, next_block: &mut Block, with_xor: bool) {
+/// Applies the compression function G to the previous and reference blocks.
+pub fn fill_block(prev_block: &Block, ref_block: &Block, next_block: &mut Block, with_xor: bool) {
+    compress(prev_block, ref_block, next_block, with_xor, None);
+}
+
+/// Applies the compression function G like `fill_block`, storing the
+/// intermediate values in `internals` when given. Quarter rounds are only
+/// recorded when `internals.rounds` is `Some`.
+pub fn compress(
+    prev_block: &Block,
+    ref_block: &Block,
+    next_block: &mut Block,
+    with_xor: bool,
+    mut internals: Option<&mut Compression>,
+) {
     let mut block_r = ref_block.clone();
     block_r ^= prev_block;
     let mut block_tmp = block_r.clone();
+    if let Some(internals) = internals.as_deref_mut() {
+        internals.r = block_r.as_u8().into();
+    }
 
     // Now block_r = ref_block + prev_block and block_tmp = ref_block + prev_block
     if with_xor {
@@ -81,94 +161,40 @@ fn fill_block(prev_block: &Block, ref_block: &Block, next_block: &mut Block, wit
         // block_tmp = ref_block + prev_block + next_block
     }
 
+    let mut rounds = internals.as_deref_mut().and_then(|i| i.rounds.take());
+
     // Apply Blake2 on columns of 64-bit words: (0,1,...,15) , then
     // (16,17,..31)... finally (112,113,...127)
     for i in 0..8 {
-        let mut v0 = block_r[16 * i];
-        let mut v1 = block_r[16 * i + 1];
-        let mut v2 = block_r[16 * i + 2];
-        let mut v3 = block_r[16 * i + 3];
-        let mut v4 = block_r[16 * i + 4];
-        let mut v5 = block_r[16 * i + 5];
-        let mut v6 = block_r[16 * i + 6];
-        let mut v7 = block_r[16 * i + 7];
-        let mut v8 = block_r[16 * i + 8];
-        let mut v9 = block_r[16 * i + 9];
-        let mut v10 = block_r[16 * i + 10];
-        let mut v11 = block_r[16 * i + 11];
-        let mut v12 = block_r[16 * i + 12];
-        let mut v13 = block_r[16 * i + 13];
-        let mut v14 = block_r[16 * i + 14];
-        let mut v15 = block_r[16 * i + 15];
-
-        p(
-            &mut v0, &mut v1, &mut v2, &mut v3, &mut v4, &mut v5, &mut v6, &mut v7, &mut v8,
-            &mut v9, &mut v10, &mut v11, &mut v12, &mut v13, &mut v14, &mut v15,
-        );
-
-        block_r[16 * i] = v0;
-        block_r[16 * i + 1] = v1;
-        block_r[16 * i + 2] = v2;
-        block_r[16 * i + 3] = v3;
-        block_r[16 * i + 4] = v4;
-        block_r[16 * i + 5] = v5;
-        block_r[16 * i + 6] = v6;
-        block_r[16 * i + 7] = v7;
-        block_r[16 * i + 8] = v8;
-        block_r[16 * i + 9] = v9;
-        block_r[16 * i + 10] = v10;
-        block_r[16 * i + 11] = v11;
-        block_r[16 * i + 12] = v12;
-        block_r[16 * i + 13] = v13;
-        block_r[16 * i + 14] = v14;
-        block_r[16 * i + 15] = v15;
+        let mut words = [0usize; 16];
+        for (j, word) in words.iter_mut().enumerate() {
+            *word = 16 * i + j;
+        }
+        p(&mut block_r, &words, rounds.as_mut());
+    }
+    if let Some(internals) = internals.as_deref_mut() {
+        internals.q = block_r.as_u8().into();
     }
 
     // Apply Blake2 on rows of 64-bit words: (0,1,16,17,...112,113), then
     // (2,3,18,19,...,114,115).. finally (14,15,30,31,...,126,127)
     for i in 0..8 {
-        let mut v0 = block_r[2 * i];
-        let mut v1 = block_r[2 * i + 1];
-        let mut v2 = block_r[2 * i + 16];
-        let mut v3 = block_r[2 * i + 17];
-        let mut v4 = block_r[2 * i + 32];
-        let mut v5 = block_r[2 * i + 33];
-        let mut v6 = block_r[2 * i + 48];
-        let mut v7 = block_r[2 * i + 49];
-        let mut v8 = block_r[2 * i + 64];
-        let mut v9 = block_r[2 * i + 65];
-        let mut v10 = block_r[2 * i + 80];
-        let mut v11 = block_r[2 * i + 81];
-        let mut v12 = block_r[2 * i + 96];
-        let mut v13 = block_r[2 * i + 97];
-        let mut v14 = block_r[2 * i + 112];
-        let mut v15 = block_r[2 * i + 113];
-
-        p(
-            &mut v0, &mut v1, &mut v2, &mut v3, &mut v4, &mut v5, &mut v6, &mut v7, &mut v8,
-            &mut v9, &mut v10, &mut v11, &mut v12, &mut v13, &mut v14, &mut v15,
-        );
-
-        block_r[2 * i] = v0;
-        block_r[2 * i + 1] = v1;
-        block_r[2 * i + 16] = v2;
-        block_r[2 * i + 17] = v3;
-        block_r[2 * i + 32] = v4;
-        block_r[2 * i + 33] = v5;
-        block_r[2 * i + 48] = v6;
-        block_r[2 * i + 49] = v7;
-        block_r[2 * i + 64] = v8;
-        block_r[2 * i + 65] = v9;
-        block_r[2 * i + 80] = v10;
-        block_r[2 * i + 81] = v11;
-        block_r[2 * i + 96] = v12;
-        block_r[2 * i + 97] = v13;
-        block_r[2 * i + 112] = v14;
-        block_r[2 * i + 113] = v15;
+        let mut words = [0usize; 16];
+        for (j, word) in words.iter_mut().enumerate() {
+            *word = 2 * i + 16 * (j / 2) + j % 2;
+        }
+        p(&mut block_r, &words, rounds.as_mut());
+    }
+    if let Some(internals) = internals.as_deref_mut() {
+        internals.z = block_r.as_u8().into();
     }
 
     block_tmp.copy_to(next_block);
     *next_block ^= &block_r;
+    if let Some(internals) = internals {
+        internals.output = next_block.as_u8().into();
+        internals.rounds = rounds;
+    }
 }
 
 fn fill_first_blocks(
@@ -182,38 +208,63 @@ fn fill_first_blocks(
         // H'(H0||0||i)
         h0[start..(start + 4)].clone_from_slice(&u32::to_le_bytes(0));
         h0[(start + 4)..(start + 8)].clone_from_slice(&u32::to_le_bytes(lane));
+        fill_first_block(context, memory, h0, lane, 0, state);
 
-        hprime(memory[(lane, 0)].as_u8_mut(), &h0);
-        let mut block = &memory[(lane, 0)];
-        state.state.set_value(
-            Argon2State::memory_state_key(lane, 0, 0),
-            Argon2Value::builder()
-                .first_param(from_utf8_lossy(&h0[0..common::PREHASH_DIGEST_LENGTH]).to_string())
-                .second_param(from_utf8_lossy(&h0[start..start + 8]).to_string())
-                .hash(from_utf8_lossy(block.as_u8()).to_string())
-                .build(),
-        );
         // H'(H0||1||i)
         h0[start..(start + 4)].clone_from_slice(&u32::to_le_bytes(1));
-        hprime(memory[(lane, 1)].as_u8_mut(), &h0);
-        block = &memory[(lane, 0)];
-        state.state.set_value(
-            Argon2State::memory_state_key(lane, 1, 0),
-            Argon2Value::builder()
-                .first_param(from_utf8_lossy(&h0[0..common::PREHASH_DIGEST_LENGTH]).to_string())
-                .second_param(from_utf8_lossy(&h0[start..start + 8]).to_string())
-                .hash(from_utf8_lossy(block.as_u8()).to_string())
-                .build(),
-        );
+        fill_first_block(context, memory, h0, lane, 1, state);
     }
 }
 
+fn fill_first_block(
+    context: &Context,
+    memory: &mut Memory,
+    h0: &[u8],
+    lane: u32,
+    index: u32,
+    state: &mut Argon2Result,
+) {
+    let block = memory[(lane, index)].as_u8_mut();
+    if !context
+        .config
+        .trace
+        .records_block(TraceLevel::Blocks, 0, lane, 0, index)
+    {
+        hprime(block, h0);
+        return;
+    }
+
+    let mut internals = Hprime::default();
+    expand(block, h0, Some(&mut internals));
+    state.record(TraceEvent::Hprime(internals));
+    let start = common::PREHASH_DIGEST_LENGTH;
+    state.record(TraceEvent::BlockInit(BlockInit {
+        lane,
+        index,
+        h0: h0[0..start].into(),
+        seed: h0[start..start + 8].into(),
+        content: memory[(lane, index)].as_u8().into(),
+    }));
+}
+
 #[cfg(feature = "crossbeam-utils")]
-fn fill_memory_blocks_mt(context: &Context, memory: &mut Memory) {
-    for p in 0..context.config.time_cost {
+fn fill_memory_blocks_mt(
+    context: &Context,
+    memory: &mut Memory,
+    state: &mut Argon2Result,
+    hooks: &mut Hooks,
+) -> Result<()> {
+    let mut lane_states: Vec<Argon2Result> = (0..context.config.lanes)
+        .map(|_| Argon2Result::new())
+        .collect();
+    for p in 0..passes(context) {
         for s in 0..common::SYNC_POINTS {
+            hooks.check_cancelled()?;
             let _ = scope(|scoped| {
-                for (l, mem) in (0..context.config.lanes).zip(memory.as_lanes_mut()) {
+                let lanes = (0..context.config.lanes)
+                    .zip(memory.as_lanes_mut())
+                    .zip(lane_states.iter_mut());
+                for ((l, mem), lane_state) in lanes {
                     let position = Position {
                         pass: p,
                         lane: l,
@@ -221,45 +272,86 @@ fn fill_memory_blocks_mt(context: &Context, memory: &mut Memory) {
                         index: 0,
                     };
                     scoped.spawn(move |_| {
-                        fill_segment(context, &position, mem, &mut Argon2Result::new());
+                        fill_segment(context, &position, context.segment_length, mem, lane_state);
                     });
                 }
             });
+            // Merge in lane order so the trace matches the sequential one.
+            for lane_state in lane_states.iter_mut() {
+                state.trace.append(&mut lane_state.trace);
+            }
+            state.flush(hooks.sink);
+            hooks.report(Progress::new(context, p, s, context.config.lanes - 1));
+            snapshot::record(context, memory, state, p, s);
         }
     }
+    Ok(())
 }
 
 #[cfg(not(feature = "crossbeam-utils"))]
-fn fill_memory_blocks_mt(_: &Context, _: &mut Memory) {
+fn fill_memory_blocks_mt(
+    _: &Context,
+    _: &mut Memory,
+    _: &mut Argon2Result,
+    _: &mut Hooks,
+) -> Result<()> {
     unimplemented!()
 }
 
-fn fill_memory_blocks_st(context: &Context, memory: &mut Memory, state: &mut Argon2Result) {
-    for p in 0..context.config.time_cost {
+fn fill_memory_blocks_st(
+    context: &Context,
+    memory: &mut Memory,
+    state: &mut Argon2Result,
+    hooks: &mut Hooks,
+) -> Result<()> {
+    for p in 0..passes(context) {
         for s in 0..common::SYNC_POINTS {
             for l in 0..context.config.lanes {
+                hooks.check_cancelled()?;
                 let position = Position {
                     pass: p,
                     lane: l,
                     slice: s,
                     index: 0,
                 };
-                fill_segment(context, &position, memory, state);
+                fill_segment(context, &position, context.segment_length, memory, state);
+                state.flush(hooks.sink);
+                hooks.report(Progress::new(context, p, s, l));
             }
+            snapshot::record(context, memory, state, p, s);
         }
     }
+    Ok(())
+}
+
+/// Returns whether the references of `slice` of `pass` are taken from
+/// address blocks instead of from the previous block.
+pub fn data_independent_addressing(context: &Context, pass: u32, slice: u32) -> bool {
+    (context.config.variant == Variant::Argon2i)
+        || (context.config.variant == Variant::Argon2id && pass == 0)
+            && (slice < (common::SYNC_POINTS / 2))
 }
 
-fn fill_segment(
+/// Fills the blocks of the segment at `position`, starting at
+/// `position.index` and stopping before index `end`.
+#[allow(clippy::explicit_counter_loop)]
+pub fn fill_segment(
     context: &Context,
     position: &Position,
+    end: u32,
     memory: &mut Memory,
     state: &mut Argon2Result,
 ) {
     let mut position = position.clone();
-    let data_independent_addressing = (context.config.variant == Variant::Argon2i)
-        || (context.config.variant == Variant::Argon2id && position.pass == 0)
-            && (position.slice < (common::SYNC_POINTS / 2));
+    let trace = &context.config.trace;
+    let trace_addresses = trace.records_segment(
+        TraceLevel::References,
+        position.pass,
+        position.lane,
+        position.slice,
+    );
+    let data_independent_addressing =
+        data_independent_addressing(context, position.pass, position.slice);
     let zero_block = Block::zero();
     let mut input_block = Block::zero();
     let mut address_block = Block::zero();
@@ -273,14 +365,25 @@ fn fill_segment(
         input_block[5] = context.config.variant.as_u64();
     }
 
-    let mut starting_index = 0u32;
-
-    if position.pass == 0 && position.slice == 0 {
-        starting_index = 2;
+    // The first two blocks of each lane are filled by fill_first_blocks.
+    let starting_index = if position.pass == 0 && position.slice == 0 {
+        position.index.max(2)
+    } else {
+        position.index
+    };
 
-        // Don't forget to generate the first block of addresses:
-        if data_independent_addressing {
+    // The counter of the address blocks before the starting index, so a
+    // resumed segment continues with the address block holding its first
+    // block. Don't forget to generate that block when the loop does not
+    // start at a block boundary. When resuming a segment the block was
+    // already recorded by the call that generated it first.
+    if data_independent_addressing {
+        input_block[6] = (starting_index / common::ADDRESSES_IN_BLOCK) as u64;
+        if starting_index < end && starting_index % common::ADDRESSES_IN_BLOCK != 0 {
             next_addresses(&mut address_block, &mut input_block, &zero_block);
+            if trace_addresses && position.pass == 0 && position.slice == 0 && starting_index == 2 {
+                state.record(address_block_event(&input_block, &address_block));
+            }
         }
     }
 
@@ -296,7 +399,7 @@ fn fill_segment(
     };
 
     let mut pseudo_rand;
-    for i in starting_index..context.segment_length {
+    for i in starting_index..end {
         // 1.1 Rotating prev_offset if needed
         if curr_offset % context.lane_length == 1 {
             prev_offset = curr_offset - 1;
@@ -307,84 +410,104 @@ fn fill_segment(
         if data_independent_addressing {
             if i % common::ADDRESSES_IN_BLOCK == 0 {
                 next_addresses(&mut address_block, &mut input_block, &zero_block);
+                if trace_addresses {
+                    state.record(address_block_event(&input_block, &address_block));
+                }
             }
             pseudo_rand = address_block[(i % common::ADDRESSES_IN_BLOCK) as usize];
         } else {
-            pseudo_rand = memory[(prev_offset)][0];
+            pseudo_rand = memory[prev_offset][0];
         }
 
-        // 1.2.2 Computing the lane of the reference block
-        // If (position.pass == 0) && (position.slice == 0): can not reference other lanes yet
-        let ref_lane = if (position.pass == 0) && (position.slice == 0) {
-            position.lane as u64
-        } else {
-            (pseudo_rand >> 32) % context.config.lanes as u64
-        };
-
-        // 1.2.3 Computing the number of possible reference block within the lane.
+        // 1.2.2 Computing the lane and index of the reference block
         position.index = i;
-        let pseudo_rand_u32 = (pseudo_rand & 0xFFFF_FFFF) as u32;
-        let same_lane = ref_lane == (position.lane as u64);
-        let ref_index = index_alpha(context, &position, pseudo_rand_u32, same_lane);
-        let current_val =
-            state
-                .state
-                .get_memory_state_value(position.lane, position.index, position.pass);
-
-        get_ref_lane(position.pass, position.slice, position.lane, ref_lane).and_then(|ref_lane| {
-            let updated_val = Argon2ValueBuilder::from_argon2_value(current_val)
-                .ref_lane(ref_lane)
-                .ref_index(ref_index.clone().to_string())
-                .build();
-            state.state.set_memory_state_value(
-                position.lane,
-                position.index,
+        let selection = select_reference(context, &position, pseudo_rand);
+        let (ref_lane, ref_index) = (selection.ref_lane as u64, selection.ref_index);
+        let lane_index = curr_offset % context.lane_length;
+        let records = |level| {
+            trace.records_block(
+                level,
                 position.pass,
-                updated_val,
-            );
-            Some(())
-        });
+                position.lane,
+                position.slice,
+                lane_index,
+            )
+        };
+        if records(TraceLevel::References) {
+            state.record(TraceEvent::ReferenceSelection(selection));
+        }
+
         // 2 Creating a new block
         let index = context.lane_length as u64 * ref_lane + ref_index as u64;
+        let with_xor = context.config.version != Version::Version10 && position.pass != 0;
         let mut curr_block = memory[curr_offset].clone();
         {
             let prev_block = &memory[prev_offset];
             let ref_block = &memory[index];
-            if context.config.version == Version::Version10 || position.pass == 0 {
-                fill_block(prev_block, ref_block, &mut curr_block, false);
+            if records(TraceLevel::Compression) {
+                let mut internals = Compression {
+                    pass: position.pass,
+                    lane: position.lane,
+                    slice: position.slice,
+                    index: lane_index,
+                    rounds: if records(TraceLevel::QuarterRounds) {
+                        Some(Vec::new())
+                    } else {
+                        None
+                    },
+                    ..Compression::default()
+                };
+                compress(
+                    prev_block,
+                    ref_block,
+                    &mut curr_block,
+                    with_xor,
+                    Some(&mut internals),
+                );
+                state.record(TraceEvent::Compression(internals));
             } else {
-                fill_block(prev_block, ref_block, &mut curr_block, true);
+                fill_block(prev_block, ref_block, &mut curr_block, with_xor);
             }
         }
 
+        if records(TraceLevel::Blocks) {
+            state.record(TraceEvent::BlockFill(BlockFill {
+                pass: position.pass,
+                lane: position.lane,
+                slice: position.slice,
+                index: lane_index,
+                prev: BlockCoord {
+                    lane: prev_offset / context.lane_length,
+                    index: prev_offset % context.lane_length,
+                },
+                reference: BlockCoord {
+                    lane: ref_lane as u32,
+                    index: ref_index,
+                },
+                with_xor,
+                content: curr_block.as_u8().into(),
+            }));
+        }
+
         memory[curr_offset] = curr_block;
         curr_offset += 1;
         prev_offset += 1;
     }
 }
 
-fn get_ref_lane(pass: u32, slice: u32, lane: u32, pseudo_rand: u64) -> Option<String> {
-    if (pass == 0) && (slice == 0) {
-        Some(lane.to_string())
-    } else if pass > 0 {
-        Some(pseudo_rand.to_string())
-    } else {
-        None
-    }
-}
-
-fn g(a: &mut u64, b: &mut u64, c: &mut u64, d: &mut u64) {
-    *a = f_bla_mka(*a, *b);
-    *d = rotr64(*d ^ *a, 32);
-    *c = f_bla_mka(*c, *d);
-    *b = rotr64(*b ^ *c, 24);
-    *a = f_bla_mka(*a, *b);
-    *d = rotr64(*d ^ *a, 16);
-    *c = f_bla_mka(*c, *d);
-    *b = rotr64(*b ^ *c, 63);
+fn g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize) {
+    v[a] = f_bla_mka(v[a], v[b]);
+    v[d] = rotr64(v[d] ^ v[a], 32);
+    v[c] = f_bla_mka(v[c], v[d]);
+    v[b] = rotr64(v[b] ^ v[c], 24);
+    v[a] = f_bla_mka(v[a], v[b]);
+    v[d] = rotr64(v[d] ^ v[a], 16);
+    v[c] = f_bla_mka(v[c], v[d]);
+    v[b] = rotr64(v[b] ^ v[c], 63);
 }
 
-fn h0(context: &Context) -> [u8; common::PREHASH_SEED_LENGTH] {
+/// Computes the H0 prehash digest, leaving room for the block seed.
+pub fn h0(context: &Context) -> [u8; common::PREHASH_SEED_LENGTH] {
     let input = [
         &u32::to_le_bytes(context.config.lanes),
         &u32::to_le_bytes(context.config.hash_length),
@@ -406,16 +529,67 @@ fn h0(context: &Context) -> [u8; common::PREHASH_SEED_LENGTH] {
     out
 }
 
-fn hprime(out: &mut [u8], input: &[u8]) {
+/// Gets the input fields of H0 in the order they are hashed.
+pub fn h0_fields(context: &Context) -> Vec<PrehashField> {
+    let number = |name: &str, value: u32| PrehashField {
+        name: name.to_string(),
+        value: Some(value),
+        bytes: u32::to_le_bytes(value)[..].into(),
+    };
+    let bytes = |name: &str, value: &[u8]| PrehashField {
+        name: name.to_string(),
+        value: None,
+        bytes: value.into(),
+    };
+    vec![
+        number("lanes", context.config.lanes),
+        number("hash_length", context.config.hash_length),
+        number("mem_cost", context.config.mem_cost),
+        number("time_cost", context.config.time_cost),
+        number("version", context.config.version.as_u32()),
+        number("variant", context.config.variant.as_u32()),
+        number("pwd_length", context.pwd.len() as u32),
+        bytes("pwd", context.pwd),
+        number("salt_length", context.salt.len() as u32),
+        bytes("salt", context.salt),
+        number("secret_length", context.config.secret.len() as u32),
+        bytes("secret", context.config.secret),
+        number("ad_length", context.config.ad.len() as u32),
+        bytes("ad", context.config.ad),
+    ]
+}
+
+/// Computes the variable-length hash function H'.
+pub fn hprime(out: &mut [u8], input: &[u8]) {
+    expand(out, input, None);
+}
+
+/// Computes the variable-length hash function H', recording every V_i and
+/// the parts copied to the output in `internals` when supplied.
+pub fn expand(out: &mut [u8], input: &[u8], internals: Option<&mut Hprime>) {
     let out_len = out.len();
+    let mut v = Vec::new();
+    let mut parts = Vec::new();
+    let mut record = |v_i: &[u8], offset: usize, length: usize| {
+        if internals.is_some() {
+            parts.push(HprimePart {
+                v: v.len() as u32,
+                offset: offset as u32,
+                length: length as u32,
+            });
+            v.push(v_i.into());
+        }
+    };
     if out_len <= common::BLAKE2B_OUT_LENGTH {
         blake2b(out, &[&u32::to_le_bytes(out_len as u32), input]);
+        record(out, 0, out_len);
     } else {
         let ai_len = 32;
         let mut out_buffer = [0u8; common::BLAKE2B_OUT_LENGTH];
         let mut in_buffer = [0u8; common::BLAKE2B_OUT_LENGTH];
         blake2b(&mut out_buffer, &[&u32::to_le_bytes(out_len as u32), input]);
         out[0..ai_len].clone_from_slice(&out_buffer[0..ai_len]);
+        record(&out_buffer, 0, ai_len);
         let mut out_pos = ai_len;
         let mut to_produce = out_len - ai_len;
 
@@ -423,14 +597,43 @@ fn hprime(out: &mut [u8], input: &[u8]) {
             in_buffer.clone_from_slice(&out_buffer);
             blake2b(&mut out_buffer, &[&in_buffer]);
             out[out_pos..out_pos + ai_len].clone_from_slice(&out_buffer[0..ai_len]);
+            record(&out_buffer, out_pos, ai_len);
             out_pos += ai_len;
             to_produce -= ai_len;
         }
         blake2b(&mut out[out_pos..out_len], &[&out_buffer]);
+        record(&out[out_pos..out_len], out_pos, out_len - out_pos);
+    }
+    if let Some(internals) = internals {
+        *internals = Hprime {
+            length: out_len as u32,
+            input: input.into(),
+            v,
+            parts,
+            output: out.to_vec().into(),
+        };
     }
 }
 
-fn index_alpha(context: &Context, position: &Position, pseudo_rand: u32, same_lane: bool) -> u32 {
+/// Maps the pseudo-random value J1 || J2 of the block at `position` to its
+/// reference block, keeping the intermediate values of the mapping.
+pub fn select_reference(
+    context: &Context,
+    position: &Position,
+    pseudo_rand: u64,
+) -> ReferenceSelection {
+    let j1 = (pseudo_rand & 0xFFFF_FFFF) as u32;
+    let j2 = (pseudo_rand >> 32) as u32;
+
+    // If (position.pass == 0) && (position.slice == 0): can not reference other lanes yet
+    let ref_lane = if (position.pass == 0) && (position.slice == 0) {
+        position.lane
+    } else {
+        j2 % context.config.lanes
+    };
+    let same_lane = ref_lane == position.lane;
+
+    // 1.2.3 Computing the number of possible reference block within the lane.
     // Pass 0:
     // - This lane: all already finished segments plus already constructed blocks in this segment
     // - Other lanes: all already finished segments
@@ -460,10 +663,10 @@ fn index_alpha(context: &Context, position: &Position, pseudo_rand: u32, same_la
             context.lane_length - context.segment_length
         }
     };
-    let reference_area_size = reference_area_size as u64;
-    let mut relative_position = pseudo_rand as u64;
+    let area = reference_area_size as u64;
+    let mut relative_position = j1 as u64;
     relative_position = (relative_position * relative_position) >> 32;
-    relative_position = reference_area_size - 1 - ((reference_area_size * relative_position) >> 32);
+    relative_position = area - 1 - ((area * relative_position) >> 32);
 
     // 1.2.5 Computing starting position
     let start_position: u32 = if position.pass != 0 {
@@ -475,50 +678,99 @@ fn index_alpha(context: &Context, position: &Position, pseudo_rand: u32, same_la
     } else {
         0u32
     };
-    let start_position = start_position as u64;
 
     // 1.2.6. Computing absolute position
-    ((start_position + relative_position) % context.lane_length as u64) as u32
+    let ref_index =
+        ((start_position as u64 + relative_position) % context.lane_length as u64) as u32;
+
+    ReferenceSelection {
+        pass: position.pass,
+        lane: position.lane,
+        slice: position.slice,
+        index: position.slice * context.segment_length + position.index,
+        j1,
+        j2,
+        same_lane,
+        reference_area_size,
+        relative_position: relative_position as u32,
+        start_position,
+        ref_lane,
+        ref_index,
+    }
 }
 
 fn len_as_32le(slice: &[u8]) -> [u8; 4] {
     u32::to_le_bytes(slice.len() as u32)
 }
 
-fn next_addresses(address_block: &mut Block, input_block: &mut Block, zero_block: &Block) {
+/// Creates the trace event for an address block generated from the input
+/// block.
+fn address_block_event(input_block: &Block, address_block: &Block) -> TraceEvent {
+    TraceEvent::AddressBlock(AddressBlock {
+        pass: input_block[0] as u32,
+        lane: input_block[1] as u32,
+        slice: input_block[2] as u32,
+        memory_blocks: input_block[3] as u32,
+        time_cost: input_block[4] as u32,
+        variant: input_block[5] as u32,
+        counter: input_block[6] as u32,
+        addresses: (0..common::ADDRESSES_IN_BLOCK as usize)
+            .map(|i| [address_block[i] as u32, (address_block[i] >> 32) as u32])
+            .collect(),
+    })
+}
+
+/// Generates the next block of pseudo-random addresses, incrementing the
+/// counter in the input block.
+pub fn next_addresses(address_block: &mut Block, input_block: &mut Block, zero_block: &Block) {
     input_block[6] += 1;
     fill_block(zero_block, input_block, address_block, false);
     fill_block(zero_block, &address_block.clone(), address_block, false);
 }
 
-fn p(
-    v0: &mut u64,
-    v1: &mut u64,
-    v2: &mut u64,
-    v3: &mut u64,
-    v4: &mut u64,
-    v5: &mut u64,
-    v6: &mut u64,
-    v7: &mut u64,
-    v8: &mut u64,
-    v9: &mut u64,
-    v10: &mut u64,
-    v11: &mut u64,
-    v12: &mut u64,
-    v13: &mut u64,
-    v14: &mut u64,
-    v15: &mut u64,
-) {
-    g(v0, v4, v8, v12);
-    g(v1, v5, v9, v13);
-    g(v2, v6, v10, v14);
-    g(v3, v7, v11, v15);
-    g(v0, v5, v10, v15);
-    g(v1, v6, v11, v12);
-    g(v2, v7, v8, v13);
-    g(v3, v4, v9, v14);
+/// Applies the BlaMka permutation P to the 16 words of the block at the
+/// indices in `words`, recording every quarter round in `rounds` when given.
+fn p(block: &mut Block, words: &[usize; 16], mut rounds: Option<&mut Vec<QuarterRound>>) {
+    const QUARTER_ROUNDS: [[usize; 4]; 8] = [
+        [0, 4, 8, 12],
+        [1, 5, 9, 13],
+        [2, 6, 10, 14],
+        [3, 7, 11, 15],
+        [0, 5, 10, 15],
+        [1, 6, 11, 12],
+        [2, 7, 8, 13],
+        [3, 4, 9, 14],
+    ];
+
+    let mut v = [0u64; 16];
+    for (value, &word) in v.iter_mut().zip(words.iter()) {
+        *value = block[word];
+    }
+
+    for &[a, b, c, d] in QUARTER_ROUNDS.iter() {
+        match rounds.as_deref_mut() {
+            Some(rounds) => {
+                let input = words_as_bytes(&[v[a], v[b], v[c], v[d]]);
+                g(&mut v, a, b, c, d);
+                rounds.push(QuarterRound {
+                    words: [words[a], words[b], words[c], words[d]].map(|w| w as u32),
+                    input: input.into(),
+                    output: words_as_bytes(&[v[a], v[b], v[c], v[d]]).into(),
+                });
+            }
+            None => g(&mut v, a, b, c, d),
+        }
+    }
+
+    for (value, &word) in v.iter().zip(words.iter()) {
+        block[word] = *value;
+    }
 }
 
 fn rotr64(w: u64, c: u32) -> u64 {
-    (w >> c) | (w << (64 - c))
+    w.rotate_right(c)
+}
+
+fn words_as_bytes(words: &[u64]) -> Vec<u8> {
+    words.iter().flat_map(|w| u64::to_le_bytes(*w)).collect()
 }
@@ -12,7 +12,6 @@ use crate::error::Error;
 use crate::result::Result;
 use crate::variant::Variant;
 use crate::version::Version;
-use base64;
 
 /// Structure containing the options.
 struct Options {
@@ -73,7 +72,7 @@ pub fn decode_string(encoded: &str) -> Result<Decoded> {
 }
 
 fn decode_empty(str: &str) -> Result<()> {
-    if str == "" {
+    if str.is_empty() {
         Ok(())
     } else {
         Err(Error::DecodingFail)
@@ -158,6 +157,8 @@ pub fn num_len(number: u32) -> u32 {
 #[cfg(test)]
 mod tests {
 
+    #[cfg(feature = "crossbeam-utils")]
+    use crate::bytes::ByteEncoding;
     #[cfg(feature = "crossbeam-utils")]
     use crate::config::Config;
     #[cfg(feature = "crossbeam-utils")]
@@ -168,7 +169,10 @@ mod tests {
     use crate::encoding::{base64_len, decode_string, num_len};
     use crate::error::Error;
     #[cfg(feature = "crossbeam-utils")]
+    use crate::snapshot::SnapshotMode;
+    #[cfg(feature = "crossbeam-utils")]
     use crate::thread_mode::ThreadMode;
+    use crate::trace::TraceOptions;
     use crate::variant::Variant;
     use crate::version::Version;
 
@@ -377,7 +381,10 @@ mod tests {
             time_cost: 3,
             variant: Variant::Argon2i,
             version: Version::Version13,
-            stop_at_iteration: 0,
+            stop_at_iteration: None,
+            trace_encoding: ByteEncoding::default(),
+            trace: TraceOptions::default(),
+            snapshot: SnapshotMode::Off,
         };
         let pwd = b"password".to_vec();
         let salt = b"salt1234".to_vec();
@@ -8,13 +8,12 @@
 
 use std::{error, fmt};
 
-#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
 
 /// Error type for Argon2 errors.
-#[derive(Clone, Debug, PartialEq)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Error {
     /// The output (hash) is too short (minimum is 4).
     OutputTooShort,
@@ -49,6 +48,7 @@ pub enum Error {
     /// The time cost (passes) is too small (minimum is 1).
     TimeTooSmall,
 
+    /// The pass to stop at is not smaller than the time cost.
     StopAtIterationTooLarge,
 
     /// The time cost (passes) is too large (maximum is 2^32 - 1).
@@ -74,9 +74,29 @@ pub enum Error {
 
     /// The decoding of the encoded data has failed.
     DecodingFail,
+
+    /// The version of a binary trace is not supported.
+    IncorrectTraceVersion,
+
+    /// The memories of two runs do not have the same lanes and lane length.
+    MemoryLayoutMismatch,
+
+    /// The run was cancelled through its cancellation token.
+    Cancelled,
+
+    /// A value of the JSON configuration is incorrect.
+    IncorrectConfig,
+
+    /// An argument is outside its allowed values.
+    IncorrectArgument,
 }
 
 impl Error {
+    /// Gets the name of the variant, such as `SaltTooShort`.
+    pub fn name(&self) -> String {
+        format!("{:?}", self)
+    }
+
     fn msg(&self) -> &str {
         match *self {
             Error::OutputTooShort => "Output is too short",
@@ -98,6 +118,11 @@ impl Error {
             Error::IncorrectType => "There is no such type of Argon2",
             Error::IncorrectVersion => "There is no such version of Argon2",
             Error::DecodingFail => "Decoding failed",
+            Error::IncorrectTraceVersion => "There is no such version of the trace format",
+            Error::Cancelled => "The run was cancelled",
+            Error::IncorrectConfig => "The configuration has an incorrect value",
+            Error::IncorrectArgument => "An argument has an incorrect value",
+            Error::MemoryLayoutMismatch => "The memory layouts of the runs differ",
             Error::StopAtIterationTooLarge => "Stop at iteration must be smaller than time cost",
         }
     }
@@ -124,3 +149,37 @@ impl From<base64::DecodeError> for Error {
         Error::DecodingFail
     }
 }
+
+impl From<serde_json::Error> for Error {
+    fn from(_: serde_json::Error) -> Self {
+        Error::DecodingFail
+    }
+}
+
+/// Converts the error to a JavaScript `Error` whose name is the name of the
+/// variant.
+impl From<Error> for JsValue {
+    fn from(error: Error) -> Self {
+        let js_error = js_sys::Error::new(error.msg());
+        js_error.set_name(&error.name());
+        js_error.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::Error;
+
+    #[test]
+    fn name_returns_variant_name() {
+        assert_eq!(Error::SaltTooShort.name(), "SaltTooShort");
+        assert_eq!(Error::IncorrectConfig.name(), "IncorrectConfig");
+    }
+
+    #[test]
+    fn json_errors_convert_to_decoding_fail() {
+        let error = serde_json::from_str::<u32>("{").unwrap_err();
+        assert_eq!(Error::from(error), Error::DecodingFail);
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::common;
+use crate::config::Config;
+use crate::context;
+use crate::error::Error;
+use crate::result::{Argon2Result, JsResult, Result};
+use crate::trace::{Trace, TraceEvent, TraceLevel};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+/// The output format of a dependency graph.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GraphFormat {
+    /// Graphviz DOT.
+    Dot,
+
+    /// GraphML.
+    GraphMl,
+
+    /// Compact JSON adjacency list.
+    Json,
+}
+
+impl GraphFormat {
+    /// Gets the string slice representation of the format.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::GraphMl => "graphml",
+            GraphFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A version of a block: the block at `(lane, index)` as written in `pass`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Node {
+    /// The pass the block was written in.
+    pub pass: u32,
+
+    /// The lane of the block.
+    pub lane: u32,
+
+    /// The index of the block within the lane.
+    pub index: u32,
+}
+
+/// The kind of dependency between two blocks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EdgeKind {
+    /// The source is the previous block of the target.
+    Prev,
+
+    /// The source is the reference block of the target.
+    Ref,
+}
+
+impl EdgeKind {
+    /// Gets the string slice representation of the edge kind.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EdgeKind::Prev => "prev",
+            EdgeKind::Ref => "ref",
+        }
+    }
+}
+
+/// A dependency of the target block on the source block. Both are positions
+/// in `Graph::nodes`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Edge {
+    /// The block that is read.
+    pub source: usize,
+
+    /// The block that is written.
+    pub target: usize,
+
+    /// The kind of dependency.
+    pub kind: EdgeKind,
+}
+
+/// Directed graph of the dependencies between the blocks of a run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Graph {
+    /// The blocks in the order they were written.
+    pub nodes: Vec<Node>,
+
+    /// The dependencies, two for every filled block.
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Serialize)]
+struct Adjacency {
+    nodes: Vec<[u32; 3]>,
+    prev: Vec<Option<usize>>,
+    #[serde(rename = "ref")]
+    reference: Vec<Option<usize>>,
+}
+
+impl Graph {
+    /// Builds the graph from the initial blocks and reference selections of
+    /// a trace, so a trace recorded at the `references` level is enough.
+    ///
+    /// The previous block of a filled block follows from its coordinates and
+    /// the reference block from its selection. Both are the latest versions
+    /// of those blocks written before it in the trace. The initial blocks
+    /// are added when they are first read if the trace does not record them.
+    /// The trace must be recorded without filters, as the edges of a block
+    /// would otherwise point at stale versions of the blocks left out.
+    pub fn from_trace(trace: &Trace, lane_length: u32) -> Graph {
+        let mut graph = Graph::default();
+        let mut latest = HashMap::new();
+        for event in trace.events.iter() {
+            match event {
+                TraceEvent::BlockInit(init) => {
+                    let node = graph.add_node(0, init.lane, init.index);
+                    latest.insert((init.lane, init.index), node);
+                }
+                TraceEvent::ReferenceSelection(selection) => {
+                    // The first block of a lane follows the last one.
+                    let prev_index = match selection.index {
+                        0 => lane_length - 1,
+                        index => index - 1,
+                    };
+                    let prev = graph.version(&mut latest, selection.lane, prev_index);
+                    let reference =
+                        graph.version(&mut latest, selection.ref_lane, selection.ref_index);
+                    let node = graph.add_node(selection.pass, selection.lane, selection.index);
+                    for (source, kind) in [(prev, EdgeKind::Prev), (reference, EdgeKind::Ref)] {
+                        if let Some(source) = source {
+                            graph.edges.push(Edge {
+                                source,
+                                target: node,
+                                kind,
+                            });
+                        }
+                    }
+                    latest.insert((selection.lane, selection.index), node);
+                }
+                _ => {}
+            }
+        }
+        graph
+    }
+
+    /// Builds the graph from the trace of a result hashed with the supplied
+    /// configuration. Fails with `Error::IncorrectConfig` when its trace
+    /// options record no reference selections or filter the blocks.
+    pub fn from_result(result: &Argon2Result, config: &Config) -> Result<Graph> {
+        context::check_layout(config)?;
+        if !config.trace.records(TraceLevel::References) || config.trace.is_filtered() {
+            return Err(Error::IncorrectConfig);
+        }
+        let lane_length = context::segment_length(config) * common::SYNC_POINTS;
+        Ok(Graph::from_trace(&result.trace, lane_length))
+    }
+
+    /// Writes the graph in the supplied format.
+    pub fn export(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::GraphMl => self.to_graphml(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Writes the graph as Graphviz DOT.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph argon2 {\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            writeln!(
+                out,
+                "  n{} [label=\"{}:{}:{}\", pass={}, lane={}, index={}];",
+                id, node.pass, node.lane, node.index, node.pass, node.lane, node.index
+            )
+            .unwrap();
+        }
+        for edge in self.edges.iter() {
+            writeln!(
+                out,
+                "  n{} -> n{} [kind={}];",
+                edge.source,
+                edge.target,
+                edge.kind.as_str()
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Writes the graph as GraphML.
+    pub fn to_graphml(&self) -> String {
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"pass\" for=\"node\" attr.name=\"pass\" attr.type=\"int\"/>\n",
+            "  <key id=\"lane\" for=\"node\" attr.name=\"lane\" attr.type=\"int\"/>\n",
+            "  <key id=\"index\" for=\"node\" attr.name=\"index\" attr.type=\"int\"/>\n",
+            "  <key id=\"kind\" for=\"edge\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <graph id=\"argon2\" edgedefault=\"directed\">\n",
+        ));
+        for (id, node) in self.nodes.iter().enumerate() {
+            writeln!(
+                out,
+                concat!(
+                    "    <node id=\"n{}\"><data key=\"pass\">{}</data>",
+                    "<data key=\"lane\">{}</data><data key=\"index\">{}</data></node>"
+                ),
+                id, node.pass, node.lane, node.index
+            )
+            .unwrap();
+        }
+        for edge in self.edges.iter() {
+            writeln!(
+                out,
+                "    <edge source=\"n{}\" target=\"n{}\"><data key=\"kind\">{}</data></edge>",
+                edge.source,
+                edge.target,
+                edge.kind.as_str()
+            )
+            .unwrap();
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    /// Writes the graph as a compact JSON adjacency list: `nodes` holds
+    /// `[pass, lane, index]` for every node and `prev` and `ref` hold the
+    /// position of the previous and reference node of every node, `null` for
+    /// the initial blocks.
+    pub fn to_json(&self) -> String {
+        let mut adjacency = Adjacency {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|n| [n.pass, n.lane, n.index])
+                .collect(),
+            prev: vec![None; self.nodes.len()],
+            reference: vec![None; self.nodes.len()],
+        };
+        for edge in self.edges.iter() {
+            match edge.kind {
+                EdgeKind::Prev => adjacency.prev[edge.target] = Some(edge.source),
+                EdgeKind::Ref => adjacency.reference[edge.target] = Some(edge.source),
+            }
+        }
+        serde_json::to_string(&adjacency).unwrap()
+    }
+
+    fn add_node(&mut self, pass: u32, lane: u32, index: u32) -> usize {
+        self.nodes.push(Node { pass, lane, index });
+        self.nodes.len() - 1
+    }
+
+    /// Gets the latest version of a block, adding the initial blocks when
+    /// they are first read.
+    fn version(
+        &mut self,
+        latest: &mut HashMap<(u32, u32), usize>,
+        lane: u32,
+        index: u32,
+    ) -> Option<usize> {
+        match latest.get(&(lane, index)) {
+            Some(node) => Some(*node),
+            None if index < 2 => {
+                let node = self.add_node(0, lane, index);
+                latest.insert((lane, index), node);
+                Some(node)
+            }
+            None => None,
+        }
+    }
+}
+
+/// Exports the dependency graph of a result returned by `hash_encoded_js`
+/// with the same configuration as `dot`, `graphml` or `json`.
+#[wasm_bindgen]
+pub fn dependency_graph_js(
+    result_json: String,
+    config_json: String,
+    format: String,
+) -> JsResult<String> {
+    let result = Argon2Result::from_json(&result_json)?;
+    let config = Config::from_json(&config_json)?;
+    let format = GraphFormat::from_str(&format).map_err(|_| Error::IncorrectArgument)?;
+    Ok(Graph::from_result(&result, &config)?.export(format))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::hash_encoded;
+    use crate::config::Config;
+    use crate::error::Error;
+    use crate::graph::{EdgeKind, Graph, GraphFormat, Node};
+    use crate::result::Argon2Result;
+    use crate::trace::{TraceLevel, TraceOptions};
+    use crate::variant::Variant;
+
+    fn graph(config: &Config) -> Graph {
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", config, &mut result).unwrap();
+        Graph::from_result(&result, config).unwrap()
+    }
+
+    #[test]
+    fn from_trace_has_a_node_per_written_block() {
+        let config = Config {
+            lanes: 2,
+            mem_cost: 16,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let graph = graph(&config);
+        assert_eq!(graph.nodes.len(), 2 * 16);
+        assert_eq!(graph.edges.len(), 2 * (2 * 16 - 4));
+        assert_eq!(
+            graph.nodes[0],
+            Node {
+                pass: 0,
+                lane: 0,
+                index: 0
+            }
+        );
+    }
+
+    #[test]
+    fn from_trace_builds_the_same_edges_from_references_level() {
+        let edges = |level| {
+            let config = Config {
+                lanes: 2,
+                mem_cost: 16,
+                time_cost: 2,
+                variant: Variant::Argon2id,
+                trace: TraceOptions {
+                    level,
+                    ..TraceOptions::default()
+                },
+                ..Config::default()
+            };
+            let graph = graph(&config);
+            let node = |id: usize| {
+                let node = graph.nodes[id];
+                (node.pass, node.lane, node.index)
+            };
+            let mut edges: Vec<_> = graph
+                .edges
+                .iter()
+                .map(|e| (node(e.source), node(e.target), e.kind.as_str()))
+                .collect();
+            edges.sort();
+            (graph.nodes.len(), edges)
+        };
+        let (nodes, references) = edges(TraceLevel::References);
+        assert_eq!(nodes, 2 * 16);
+        assert_eq!(references.len(), 2 * (2 * 16 - 4));
+        assert_eq!((nodes, references), edges(TraceLevel::Blocks));
+    }
+
+    #[test]
+    fn from_trace_links_previous_pass_for_first_block() {
+        let config = Config {
+            variant: Variant::Argon2i,
+            mem_cost: 8,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let graph = graph(&config);
+        let first = graph
+            .nodes
+            .iter()
+            .position(|n| n.pass == 1 && n.index == 0)
+            .unwrap();
+        let prev = graph
+            .edges
+            .iter()
+            .find(|e| e.target == first && e.kind == EdgeKind::Prev)
+            .unwrap();
+        assert_eq!(
+            graph.nodes[prev.source],
+            Node {
+                pass: 0,
+                lane: 0,
+                index: 7
+            }
+        );
+        assert!(graph.edges.iter().all(|e| e.source < e.target));
+    }
+
+    #[test]
+    fn from_result_rejects_filtered_traces() {
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 2,
+            trace: TraceOptions {
+                pass: Some(1..2),
+                ..TraceOptions::default()
+            },
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+        assert_eq!(
+            Graph::from_result(&result, &config),
+            Err(Error::IncorrectConfig)
+        );
+
+        let off = Config {
+            trace: TraceOptions::off(),
+            ..config
+        };
+        assert_eq!(
+            Graph::from_result(&result, &off),
+            Err(Error::IncorrectConfig)
+        );
+    }
+
+    #[test]
+    fn export_writes_every_format() {
+        let config = Config {
+            mem_cost: 8,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let graph = graph(&config);
+
+        let dot = graph.export(GraphFormat::Dot);
+        assert!(dot.starts_with("digraph argon2 {"));
+        assert!(dot.contains("n1 -> n2 [kind=prev];"));
+        assert_eq!(dot.matches(" -> ").count(), graph.edges.len());
+
+        let graphml = graph.export(GraphFormat::GraphMl);
+        assert_eq!(graphml.matches("<node ").count(), 8);
+        assert_eq!(graphml.matches("<edge ").count(), 12);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&graph.export(GraphFormat::Json)).unwrap();
+        assert_eq!(json["nodes"][2], serde_json::json!([0, 0, 2]));
+        assert_eq!(json["prev"][0], serde_json::Value::Null);
+        assert_eq!(json["prev"][2], 1);
+        assert_eq!(json["ref"].as_array().unwrap().len(), 8);
+    }
+}
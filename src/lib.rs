@@ -83,25 +83,56 @@
 //! This version uses the standard implementation and does not yet implement
 //! optimizations. Therefore, it is not the fastest implementation available.
 
+mod analysis;
 mod argon2;
+mod avalanche;
 mod block;
+mod bytes;
+mod columnar;
 mod common;
 mod config;
+mod consistency;
 mod context;
 mod core;
 mod decoded;
 mod encoding;
 mod error;
+mod graph;
 mod memory;
+mod progress;
+mod replay;
 mod result;
+mod sink;
+mod snapshot;
+mod stepper;
 mod thread_mode;
+mod trace;
 mod variant;
 mod version;
 
+pub use crate::analysis::{reference_stats_js, ReferenceStats};
 pub use crate::argon2::*;
+pub use crate::avalanche::{avalanche_js, Avalanche};
+pub use crate::bytes::{ByteEncoding, Bytes};
+pub use crate::columnar::{
+    hash_encoded_columns, hash_encoded_columns_js, ColumnarHash, TraceColumns, FORMAT_VERSION,
+    MAGIC, NO_REFERENCE,
+};
 pub use crate::config::Config;
+pub use crate::consistency::{check_trace, Mismatch};
 pub use crate::error::Error;
-pub use crate::result::Result;
+pub use crate::graph::{dependency_graph_js, Edge, EdgeKind, Graph, GraphFormat, Node};
+pub use crate::progress::{CancellationToken, Hooks, Progress};
+pub use crate::replay::{replay_js, replay_trace, Divergence, Replay};
+pub use crate::result::{Argon2Result, JsResult, Result};
+pub use crate::sink::{CallbackSink, NoopSink, TraceSink};
+pub use crate::snapshot::{snapshot_diff_js, Snapshot, SnapshotMode, DIGEST_LENGTH};
+pub use crate::stepper::{hash_encoded_async_js, Argon2Stepper};
 pub use crate::thread_mode::ThreadMode;
+pub use crate::trace::{
+    AddressBlock, BlockCoord, BlockFill, BlockInit, Compression, Finalization, Hprime, HprimePart,
+    LastBlock, Prehash, PrehashField, QuarterRound, ReferenceSelection, Trace, TraceEvent,
+    TraceLevel, TraceOptions,
+};
 pub use crate::variant::Variant;
 pub use crate::version::Version;
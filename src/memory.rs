@@ -33,6 +33,11 @@ impl Memory {
         Memory { rows, cols, blocks }
     }
 
+    /// Gets the blocks of the memory matrix, lane by lane.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
     #[cfg(feature = "crossbeam-utils")]
     /// Gets the mutable lanes representation of the memory matrix.
     pub fn as_lanes_mut(&mut self) -> Vec<&mut Memory> {
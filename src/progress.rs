@@ -0,0 +1,278 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::common;
+use crate::context::Context;
+use crate::core;
+use crate::error::Error;
+use crate::result::Result;
+use crate::sink::TraceSink;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+/// How far the memory of a run is filled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Progress {
+    /// The number of blocks filled so far, counting the initial blocks.
+    pub blocks_done: u64,
+
+    /// The number of blocks filled by the whole run.
+    pub blocks_total: u64,
+}
+
+impl Progress {
+    /// Gets the progress after `slice` of `pass` is filled up to and
+    /// including `lane`.
+    pub fn new(context: &Context, pass: u32, slice: u32, lane: u32) -> Progress {
+        let slices = pass as u64 * common::SYNC_POINTS as u64 + slice as u64;
+        let segments = slices * context.config.lanes as u64 + lane as u64 + 1;
+        Progress::after_segments(context, segments)
+    }
+
+    /// Gets the progress after the first `segments` segments, in the
+    /// sequential order, are filled.
+    pub fn after_segments(context: &Context, segments: u64) -> Progress {
+        Progress {
+            blocks_done: segments * context.segment_length as u64,
+            blocks_total: context.memory_blocks as u64 * core::passes(context) as u64,
+        }
+    }
+}
+
+/// Token to cancel a run. The run checks it between segments and stops with
+/// `Error::Cancelled`. Clones share the same state.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+
+    /// The states of the tokens this one was created from by `child`.
+    parents: Vec<Arc<AtomicBool>>,
+}
+
+#[wasm_bindgen]
+impl CancellationToken {
+    /// Creates a new token that is not cancelled.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Requests the run to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether the run was requested to stop, through this token or
+    /// a token it was created from.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self.parents.iter().any(|p| p.load(Ordering::SeqCst))
+    }
+}
+
+impl CancellationToken {
+    /// Creates a token that is cancelled when this one is, but whose own
+    /// cancellation leaves this one untouched.
+    pub fn child(&self) -> CancellationToken {
+        let mut parents = self.parents.clone();
+        parents.push(self.cancelled.clone());
+        CancellationToken {
+            cancelled: Arc::default(),
+            parents,
+        }
+    }
+}
+
+/// What a run reports to while filling the memory: the sink receiving the
+/// trace events, the progress callback and the cancellation token.
+pub struct Hooks<'a> {
+    /// The sink receiving the trace events.
+    pub sink: &'a mut dyn TraceSink,
+
+    /// The function called with the progress after every segment.
+    pub progress: Option<&'a mut dyn FnMut(Progress)>,
+
+    /// The token checked between segments.
+    pub cancel: Option<&'a CancellationToken>,
+}
+
+impl<'a> Hooks<'a> {
+    /// Creates hooks that only hand the trace events to the sink.
+    pub fn new(sink: &'a mut dyn TraceSink) -> Hooks<'a> {
+        Hooks {
+            sink,
+            progress: None,
+            cancel: None,
+        }
+    }
+
+    /// Reports the progress, if requested.
+    pub fn report(&mut self, progress: Progress) {
+        if let Some(callback) = self.progress.as_mut() {
+            callback(progress);
+        }
+    }
+
+    /// Returns `Error::Cancelled` when the run was requested to stop.
+    pub fn check_cancelled(&self) -> Result<()> {
+        match self.cancel {
+            Some(token) if token.is_cancelled() => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::hash_encoded_with_hooks;
+    use crate::config::Config;
+    use crate::context::Context;
+    use crate::error::Error;
+    use crate::progress::{CancellationToken, Hooks, Progress};
+    use crate::result::Argon2Result;
+    use crate::sink::{CallbackSink, NoopSink};
+    use crate::thread_mode::ThreadMode;
+    use crate::trace::{TraceLevel, TraceOptions};
+
+    fn config(thread_mode: ThreadMode) -> Config<'static> {
+        Config {
+            lanes: 2,
+            mem_cost: 32,
+            thread_mode,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        }
+    }
+
+    fn progress(config: &Config) -> Vec<Progress> {
+        let mut reports = Vec::new();
+        let mut sink = NoopSink;
+        let mut callback = |progress| reports.push(progress);
+        let mut hooks = Hooks {
+            progress: Some(&mut callback),
+            ..Hooks::new(&mut sink)
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded_with_hooks(b"password", b"somesalt", config, &mut result, &mut hooks).unwrap();
+        reports
+    }
+
+    #[test]
+    fn progress_is_reported_after_every_segment() {
+        let reports = progress(&config(ThreadMode::Sequential));
+        assert_eq!(reports.len(), 2 * 4 * 2);
+        assert!(reports.iter().all(|p| p.blocks_total == 64));
+        assert_eq!(reports[0].blocks_done, 4);
+        assert_eq!(reports.last().unwrap().blocks_done, 64);
+    }
+
+    #[test]
+    fn progress_is_reported_after_every_slice_in_parallel() {
+        let reports = progress(&config(ThreadMode::Parallel));
+        assert_eq!(reports.len(), 2 * 4);
+        assert_eq!(reports[0].blocks_done, 8);
+        assert_eq!(reports.last().unwrap().blocks_done, 64);
+    }
+
+    #[test]
+    fn new_does_not_overflow_for_large_runs() {
+        let config = Config {
+            lanes: 0xFFFF,
+            mem_cost: 0x20_0000,
+            time_cost: 0xFFFF_FFFF,
+            ..Config::default()
+        };
+        let context = Context::new(config, b"password", b"somesalt").unwrap();
+        let progress = Progress::new(&context, 0xFFFF_FFFE, 3, 0xFFFE);
+        assert_eq!(progress.blocks_done, progress.blocks_total);
+    }
+
+    #[test]
+    fn cancelled_token_stops_the_run() {
+        for thread_mode in [ThreadMode::Sequential, ThreadMode::Parallel] {
+            let token = CancellationToken::new();
+            let mut reports = 0;
+            let mut sink = NoopSink;
+            let mut callback = |_| {
+                reports += 1;
+                if reports == 3 {
+                    token.cancel();
+                }
+            };
+            let mut hooks = Hooks {
+                progress: Some(&mut callback),
+                cancel: Some(&token),
+                ..Hooks::new(&mut sink)
+            };
+            let mut result = Argon2Result::new();
+            let res = hash_encoded_with_hooks(
+                b"password",
+                b"somesalt",
+                &config(thread_mode),
+                &mut result,
+                &mut hooks,
+            );
+            assert_eq!(res, Err(Error::Cancelled));
+            assert!(result.hash.is_empty());
+            assert_eq!(reports, 3);
+        }
+    }
+
+    #[test]
+    fn sink_can_cancel_the_run() {
+        let token = CancellationToken::new();
+        let mut batches = 0;
+        let mut sink = CallbackSink::new(|_| {
+            batches += 1;
+            if batches == 2 {
+                token.cancel();
+            }
+        });
+        let mut hooks = Hooks {
+            cancel: Some(&token),
+            ..Hooks::new(&mut sink)
+        };
+        let mut result = Argon2Result::new();
+        let res = hash_encoded_with_hooks(
+            b"password",
+            b"somesalt",
+            &config(ThreadMode::Sequential),
+            &mut result,
+            &mut hooks,
+        );
+        assert_eq!(res, Err(Error::Cancelled));
+        assert_eq!(batches, 2);
+    }
+
+    #[test]
+    fn child_token_follows_its_parent_only() {
+        let token = CancellationToken::new();
+        let child = token.child();
+        let grandchild = child.child();
+        grandchild.cancel();
+        assert!(grandchild.is_cancelled());
+        assert!(!child.is_cancelled());
+        token.cancel();
+        assert!(child.is_cancelled());
+        assert!(token.child().child().is_cancelled());
+    }
+
+    #[test]
+    fn cancellation_token_clones_share_state() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+}
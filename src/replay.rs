@@ -0,0 +1,557 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::common;
+use crate::config::Config;
+use crate::context::Context;
+use crate::core::{self, Position};
+use crate::encoding;
+use crate::error::Error;
+use crate::memory::Memory;
+use crate::result::{Argon2Result, JsResult, Result};
+use crate::snapshot::SnapshotMode;
+use crate::trace::{
+    BlockCoord, BlockFill, BlockInit, Finalization, Trace, TraceEvent, TraceLevel, TraceOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+/// The first difference between a recorded result and its replay. `event`
+/// is the position of the recorded event in the trace.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Divergence {
+    /// The content of an initial block differs from the replayed one.
+    BlockInit { event: usize, lane: u32, index: u32 },
+
+    /// The reference block differs from the one selected by the replay.
+    Reference {
+        event: usize,
+        pass: u32,
+        lane: u32,
+        index: u32,
+    },
+
+    /// The content of a filled block differs from the replayed one.
+    BlockFill {
+        event: usize,
+        pass: u32,
+        lane: u32,
+        index: u32,
+    },
+
+    /// The tag differs from the replayed one.
+    Tag { event: usize },
+
+    /// The encoded hash of the result differs from the replayed one, or is
+    /// missing from a complete result.
+    Hash,
+
+    /// The trace holds none of the events that its trace options record.
+    MissingTrace,
+
+    /// A block of the memory of a partial result differs from the replayed
+    /// one.
+    Memory { lane: u32, index: u32 },
+
+    /// The memory of a partial result holds a different number of blocks
+    /// than the replayed one.
+    MemoryLength { expected: u32, found: u64 },
+}
+
+/// Outcome of replaying a recorded result.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Replay {
+    /// The number of recorded blocks, references and tags that matched the
+    /// replay before it stopped.
+    pub checked: u64,
+
+    /// The first difference found, `None` when the result matches.
+    pub divergence: Option<Divergence>,
+}
+
+/// Replays the run of a result from its original inputs and compares it with
+/// what was recorded.
+///
+/// Unlike `check_trace`, which recomputes every event from the inputs
+/// recorded in the trace itself, the replay only trusts the inputs. Every
+/// recorded initial block, reference and filled block, the tag and the
+/// encoded hash (or the memory of a partial result) are compared in the
+/// order in which they were computed, stopping at the first divergence. The
+/// trace may be recorded at any level and with any filters given by
+/// `config.trace`; blocks that were not recorded are replayed without being
+/// checked.
+pub fn replay_trace(
+    pwd: &[u8],
+    salt: &[u8],
+    config: &Config,
+    result: &Argon2Result,
+) -> Result<Replay> {
+    let options = config.trace.clone();
+    let config = Config {
+        trace: TraceOptions::new(TraceLevel::Blocks),
+        snapshot: SnapshotMode::Off,
+        ..config.clone()
+    };
+    let context = Context::new(config, pwd, salt)?;
+    let mut replayer = Replayer::new(&result.trace, options);
+    let divergence = replayer.run(&context, result).err();
+    Ok(Replay {
+        checked: replayer.checked,
+        divergence,
+    })
+}
+
+/// Replays a result returned by `hash_encoded_js` from the inputs it was
+/// hashed with, returning the `Replay` as JSON.
+#[wasm_bindgen]
+pub fn replay_js(
+    result_json: String,
+    pwd: String,
+    salt: String,
+    config_json: String,
+) -> JsResult<String> {
+    let result = Argon2Result::from_json(&result_json)?;
+    let config = Config::from_json(&config_json)?;
+    let replay = replay_trace(pwd.as_bytes(), salt.as_bytes(), &config, &result)?;
+    Ok(serde_json::to_string(&replay).map_err(Error::from)?)
+}
+
+/// The recorded events that are checked, by the coordinates of their block.
+struct Replayer<'a> {
+    inits: HashMap<(u32, u32), (usize, &'a BlockInit)>,
+    references: HashMap<(u32, u32, u32), (usize, BlockCoord)>,
+    fills: HashMap<(u32, u32, u32), (usize, &'a BlockFill)>,
+    finalization: Option<(usize, &'a Finalization)>,
+    options: TraceOptions,
+    expected: bool,
+    checked: u64,
+}
+
+impl<'a> Replayer<'a> {
+    fn new(trace: &'a Trace, options: TraceOptions) -> Replayer<'a> {
+        let mut replayer = Replayer {
+            inits: HashMap::new(),
+            references: HashMap::new(),
+            fills: HashMap::new(),
+            finalization: None,
+            options,
+            expected: false,
+            checked: 0,
+        };
+        for (event, entry) in trace.events.iter().enumerate() {
+            match entry {
+                TraceEvent::BlockInit(init) => {
+                    replayer
+                        .inits
+                        .insert((init.lane, init.index), (event, init));
+                }
+                TraceEvent::ReferenceSelection(selection) => {
+                    let reference = BlockCoord {
+                        lane: selection.ref_lane,
+                        index: selection.ref_index,
+                    };
+                    let key = (selection.pass, selection.lane, selection.index);
+                    replayer.references.insert(key, (event, reference));
+                }
+                TraceEvent::BlockFill(fill) => {
+                    let key = (fill.pass, fill.lane, fill.index);
+                    replayer.fills.insert(key, (event, fill));
+                }
+                TraceEvent::Finalization(finalization) => {
+                    replayer.finalization = Some((event, finalization));
+                }
+                _ => {}
+            }
+        }
+        replayer
+    }
+
+    fn run(
+        &mut self,
+        context: &Context,
+        result: &Argon2Result,
+    ) -> std::result::Result<(), Divergence> {
+        let mut memory = Memory::new(context.config.lanes, context.lane_length);
+        let mut state = Argon2Result::new();
+        core::initialize(context, &mut memory, &mut state);
+        self.compare(context, &mut state)?;
+        for p in 0..core::passes(context) {
+            for s in 0..common::SYNC_POINTS {
+                for l in 0..context.config.lanes {
+                    let position = Position {
+                        pass: p,
+                        lane: l,
+                        slice: s,
+                        index: 0,
+                    };
+                    let end = context.segment_length;
+                    core::fill_segment(context, &position, end, &mut memory, &mut state);
+                    self.compare(context, &mut state)?;
+                }
+            }
+        }
+
+        if result.partial {
+            self.check_recorded()?;
+            return self.compare_memory(context, &memory, result);
+        }
+        let hash = core::finalize(context, &memory, &mut state);
+        self.compare(context, &mut state)?;
+        self.check_recorded()?;
+        if encoding::encode_string(context, &hash) != result.hash {
+            return Err(Divergence::Hash);
+        }
+        self.checked += 1;
+        Ok(())
+    }
+
+    /// Fails when the trace options record some of the replayed events but
+    /// the trace holds none of them.
+    fn check_recorded(&self) -> std::result::Result<(), Divergence> {
+        let recorded = !self.inits.is_empty()
+            || !self.references.is_empty()
+            || !self.fills.is_empty()
+            || self.finalization.is_some();
+        if self.expected && !recorded {
+            return Err(Divergence::MissingTrace);
+        }
+        Ok(())
+    }
+
+    /// Compares the events replayed since the last call with the recorded
+    /// ones, in replay order.
+    fn compare(
+        &mut self,
+        context: &Context,
+        state: &mut Argon2Result,
+    ) -> std::result::Result<(), Divergence> {
+        for entry in state.trace.events.drain(..) {
+            match entry {
+                TraceEvent::BlockInit(init) => {
+                    self.expected |=
+                        self.options
+                            .records_block(TraceLevel::Blocks, 0, init.lane, 0, init.index);
+                    if let Some(&(event, recorded)) = self.inits.get(&(init.lane, init.index)) {
+                        if recorded.content != init.content {
+                            return Err(Divergence::BlockInit {
+                                event,
+                                lane: init.lane,
+                                index: init.index,
+                            });
+                        }
+                        self.checked += 1;
+                    }
+                }
+                TraceEvent::BlockFill(fill) => {
+                    let slice = fill.index / context.segment_length;
+                    self.expected |= self.options.records_block(
+                        TraceLevel::References,
+                        fill.pass,
+                        fill.lane,
+                        slice,
+                        fill.index,
+                    );
+                    let key = (fill.pass, fill.lane, fill.index);
+                    let reference = self.references.get(&key).copied().or_else(|| {
+                        self.fills
+                            .get(&key)
+                            .map(|&(event, recorded)| (event, recorded.reference))
+                    });
+                    let divergence = |event| Divergence::Reference {
+                        event,
+                        pass: fill.pass,
+                        lane: fill.lane,
+                        index: fill.index,
+                    };
+                    if let Some((event, reference)) = reference {
+                        if reference != fill.reference {
+                            return Err(divergence(event));
+                        }
+                        self.checked += 1;
+                    }
+                    if let Some(&(event, recorded)) = self.fills.get(&key) {
+                        if recorded.reference != fill.reference {
+                            return Err(divergence(event));
+                        }
+                        if recorded.content != fill.content {
+                            return Err(Divergence::BlockFill {
+                                event,
+                                pass: fill.pass,
+                                lane: fill.lane,
+                                index: fill.index,
+                            });
+                        }
+                        self.checked += 1;
+                    }
+                }
+                TraceEvent::Finalization(finalization) => {
+                    self.expected |= self.options.records(TraceLevel::Blocks);
+                    if let Some((event, recorded)) = self.finalization {
+                        if recorded.tag != finalization.tag {
+                            return Err(Divergence::Tag { event });
+                        }
+                        self.checked += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn compare_memory(
+        &mut self,
+        context: &Context,
+        memory: &Memory,
+        result: &Argon2Result,
+    ) -> std::result::Result<(), Divergence> {
+        if result.memory.len() as u64 != context.memory_blocks as u64 {
+            return Err(Divergence::MemoryLength {
+                expected: context.memory_blocks,
+                found: result.memory.len() as u64,
+            });
+        }
+        let blocks = memory.blocks().iter().zip(result.memory.iter());
+        for (i, (block, recorded)) in blocks.enumerate() {
+            if block.as_u8() != &recorded[..] {
+                return Err(Divergence::Memory {
+                    lane: i as u32 / context.lane_length,
+                    index: i as u32 % context.lane_length,
+                });
+            }
+            self.checked += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::hash_encoded;
+    use crate::bytes::Bytes;
+    use crate::config::Config;
+    use crate::replay::{replay_js, replay_trace, Divergence};
+    use crate::result::Argon2Result;
+    use crate::trace::{TraceEvent, TraceLevel, TraceOptions};
+    use crate::variant::Variant;
+
+    fn config(variant: Variant) -> Config<'static> {
+        Config {
+            variant,
+            lanes: 2,
+            mem_cost: 16,
+            time_cost: 2,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        }
+    }
+
+    fn hash(config: &Config) -> Argon2Result {
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", config, &mut result).unwrap();
+        result
+    }
+
+    fn flip(bytes: &mut Bytes) {
+        let mut tampered = bytes.to_vec();
+        tampered[0] ^= 1;
+        *bytes = tampered.into();
+    }
+
+    #[test]
+    fn replay_of_untampered_result_matches() {
+        for variant in [Variant::Argon2d, Variant::Argon2i, Variant::Argon2id] {
+            let config = config(variant);
+            let result = hash(&config);
+            let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+            assert_eq!(replay.divergence, None);
+            // 4 initial blocks, 28 references and fills, the tag and the hash.
+            assert_eq!(replay.checked, 4 + 2 * 28 + 2);
+        }
+    }
+
+    #[test]
+    fn replay_with_other_inputs_diverges_at_first_block() {
+        let config = config(Variant::Argon2id);
+        let result = hash(&config);
+        let replay = replay_trace(b"passwore", b"somesalt", &config, &result).unwrap();
+        assert_eq!(
+            replay.divergence,
+            Some(Divergence::BlockInit {
+                event: 2,
+                lane: 0,
+                index: 0
+            })
+        );
+        assert_eq!(replay.checked, 0);
+    }
+
+    #[test]
+    fn replay_pinpoints_tampered_block() {
+        let config = config(Variant::Argon2d);
+        let mut result = hash(&config);
+        let (event, pass, lane, index) = result
+            .trace
+            .events
+            .iter_mut()
+            .enumerate()
+            .find_map(|(event, e)| match e {
+                TraceEvent::BlockFill(fill) if fill.pass == 1 && fill.index == 5 => {
+                    flip(&mut fill.content);
+                    Some((event, fill.pass, fill.lane, fill.index))
+                }
+                _ => None,
+            })
+            .unwrap();
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(
+            replay.divergence,
+            Some(Divergence::BlockFill {
+                event,
+                pass,
+                lane,
+                index
+            })
+        );
+    }
+
+    #[test]
+    fn replay_pinpoints_tampered_reference() {
+        let config = config(Variant::Argon2i);
+        let mut result = hash(&config);
+        let event = result
+            .trace
+            .events
+            .iter_mut()
+            .position(|e| match e {
+                TraceEvent::ReferenceSelection(selection) if selection.index == 5 => {
+                    selection.ref_index = (selection.ref_index + 1) % 4;
+                    true
+                }
+                _ => false,
+            })
+            .unwrap();
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(
+            replay.divergence,
+            Some(Divergence::Reference {
+                event,
+                pass: 0,
+                lane: 0,
+                index: 5
+            })
+        );
+    }
+
+    #[test]
+    fn replay_detects_tampered_tag_and_hash() {
+        let config = config(Variant::Argon2i);
+        let mut result = hash(&config);
+        let event = result.trace.events.len() - 1;
+        if let TraceEvent::Finalization(finalization) = &mut result.trace.events[event] {
+            flip(&mut finalization.tag);
+        }
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(replay.divergence, Some(Divergence::Tag { event }));
+
+        let mut result = hash(&config);
+        result.hash.push('A');
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(replay.divergence, Some(Divergence::Hash));
+    }
+
+    #[test]
+    fn replay_detects_stripped_hash_and_trace() {
+        let config = config(Variant::Argon2id);
+        let mut result = hash(&config);
+        result.hash.clear();
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(replay.divergence, Some(Divergence::Hash));
+
+        let mut result = hash(&config);
+        result.trace.events.clear();
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(replay.divergence, Some(Divergence::MissingTrace));
+        assert_eq!(replay.checked, 0);
+
+        let config = Config {
+            trace: TraceOptions::off(),
+            ..config
+        };
+        let result = hash(&config);
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(replay.divergence, None);
+        assert_eq!(replay.checked, 1);
+    }
+
+    #[test]
+    fn replay_checks_only_recorded_blocks_of_filtered_traces() {
+        let config = Config {
+            trace: TraceOptions {
+                level: TraceLevel::Blocks,
+                pass: Some(1..2),
+                lane: Some(1..2),
+                ..TraceOptions::default()
+            },
+            ..config(Variant::Argon2d)
+        };
+        let result = hash(&config);
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(replay.divergence, None);
+        // 8 references and fills of lane 1 in pass 1, the tag and the hash.
+        assert_eq!(replay.checked, 2 * 8 + 2);
+    }
+
+    #[test]
+    fn replay_compares_memory_of_partial_results() {
+        let config = Config {
+            stop_at_iteration: Some(0),
+            ..config(Variant::Argon2d)
+        };
+        let mut result = hash(&config);
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(replay.divergence, None);
+
+        flip(&mut result.memory[8 + 3]);
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(
+            replay.divergence,
+            Some(Divergence::Memory { lane: 1, index: 3 })
+        );
+
+        result.memory.pop();
+        let replay = replay_trace(b"password", b"somesalt", &config, &result).unwrap();
+        assert_eq!(
+            replay.divergence,
+            Some(Divergence::MemoryLength {
+                expected: 16,
+                found: 15
+            })
+        );
+    }
+
+    #[test]
+    fn replay_js_replays_json_result() {
+        let config_json =
+            r#"{"memory": "16", "parallelism": "2", "iterations": "1", "trace_level": "blocks"}"#;
+        let config = Config::from_json(config_json).unwrap();
+        let result = hash(&config);
+        let json = replay_js(
+            result.to_json(),
+            "password".to_string(),
+            "somesalt".to_string(),
+            config_json.to_string(),
+        )
+        .unwrap();
+        assert_eq!(json, r#"{"checked":30,"divergence":null}"#);
+    }
+}
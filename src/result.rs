@@ -7,176 +7,201 @@
 // except according to those terms.
 
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
 
+use crate::bytes::{self, ByteEncoding, Bytes};
 use crate::error::Error;
+use crate::memory::Memory;
+use crate::sink::TraceSink;
+use crate::snapshot::Snapshot;
+use crate::trace::{Trace, TraceEvent};
 use std::result;
+use wasm_bindgen::JsValue;
 
 /// A specialized result type for Argon2 operations.
 pub type Result<T> = result::Result<T, Error>;
 
-#[derive(Default, Serialize, Deserialize, Clone)]
+/// The result type of functions exported to JavaScript, failing with the
+/// `Error` converted to a JavaScript `Error`.
+pub type JsResult<T> = result::Result<T, JsValue>;
+
+/// Structure containing the encoded hash and the trace of an Argon2 run.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Argon2Result {
+    /// The encoded hash.
     pub hash: String,
-    pub state: Argon2State,
-}
 
-#[derive(Default, Serialize, Deserialize, Clone)]
-pub struct Argon2State {
-    pub hash_map: Value,
-}
+    /// The encoding of the byte payloads in the serialized trace.
+    pub encoding: ByteEncoding,
 
-#[derive(Default, Serialize, Deserialize, Clone)]
-pub struct Argon2Value {
-    first_param: String,
-    second_param: String,
-    hash: String,
-    ref_lane: String,
-    ref_index: String,
-}
+    /// The events recorded while hashing.
+    pub trace: Trace,
 
-#[derive(Default)]
-pub struct Argon2ValueBuilder {
-    first_param: String,
-    second_param: String,
-    hash: String,
-    ref_lane: String,
-    ref_index: String,
+    /// Whether hashing stopped early, in which case there is no hash.
+    pub partial: bool,
+
+    /// The memory blocks, lane by lane, when hashing stopped early.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<Bytes>,
+
+    /// The memory snapshots taken at every sync point.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snapshots: Vec<Snapshot>,
 }
 
 impl Argon2Result {
+    /// Creates a new result with an empty hash and trace.
     pub fn new() -> Argon2Result {
+        Argon2Result::with_encoding(ByteEncoding::default())
+    }
+
+    /// Creates a new result whose byte payloads are serialized with the
+    /// specified encoding.
+    pub fn with_encoding(encoding: ByteEncoding) -> Argon2Result {
         Argon2Result {
-            hash: "".to_string(),
-            state: Argon2State::new(),
+            hash: String::new(),
+            encoding,
+            trace: Trace::new(),
+            partial: false,
+            memory: Vec::new(),
+            snapshots: Vec::new(),
         }
     }
 
-    pub fn set_hash(&mut self, hash: &String) {
+    /// Sets the encoded hash.
+    pub fn set_hash(&mut self, hash: &str) {
         self.hash = hash.to_string();
     }
 
-    pub fn to_json(self) -> String {
-        serde_json::to_string(&self).unwrap()
+    /// Marks the result as partial and stores the memory state.
+    pub fn set_partial(&mut self, memory: &Memory) {
+        self.partial = true;
+        self.memory = memory.blocks().iter().map(|b| b.as_u8().into()).collect();
     }
-}
 
-impl Argon2State {
-    pub fn new() -> Argon2State {
-        Argon2State {
-            hash_map: json!({}),
-        }
-    }
-    pub fn set_value(&mut self, key: String, value: Argon2Value) {
-        self.hash_map
-            .as_object_mut()
-            .unwrap()
-            .insert(key, value.to_json_value());
+    /// Appends an event to the trace.
+    pub fn record(&mut self, event: TraceEvent) {
+        self.trace.push(event);
     }
 
-    pub fn set_memory_state_value(
-        &mut self,
-        lane: u32,
-        index: u32,
-        iteration: u32,
-        value: Argon2Value,
-    ) {
-        self.set_value(Argon2State::memory_state_key(lane, index, iteration), value);
+    /// Moves the recorded events to the sink, if there are any.
+    pub fn flush(&mut self, sink: &mut dyn TraceSink) {
+        if !self.trace.events.is_empty() {
+            sink.write(std::mem::take(&mut self.trace.events));
+        }
     }
 
-    pub fn get_memory_state_value(&mut self, lane: u32, index: u32, iteration: u32) -> Argon2Value {
-        let value = self
-            .hash_map
-            .as_object_mut()
-            .unwrap()
-            .get(&Argon2State::memory_state_key(lane, index, iteration))
-            .unwrap_or(&Argon2Value::builder().build().to_json_value())
-            .clone();
-        serde_json::from_value(value).unwrap()
+    /// Removes the recorded events and serializes them to a JSON array.
+    pub fn take_events_json(&mut self) -> String {
+        let events = std::mem::take(&mut self.trace.events);
+        bytes::to_json(&events, self.encoding)
     }
 
-    pub fn memory_state_key(lane: u32, index: u32, iteration: u32) -> String {
-        format!("B^{}[{}][{}]", iteration, lane, index)
+    /// Serializes the result to a JSON string.
+    pub fn to_json(&self) -> String {
+        bytes::to_json(self, self.encoding)
     }
-}
 
-impl Argon2Value {
-    pub fn new(
-        first_param: String,
-        second_param: String,
-        hash: String,
-        ref_lane: String,
-        ref_index: String,
-    ) -> Argon2Value {
-        Argon2Value {
-            first_param,
-            second_param,
-            hash,
-            ref_index,
-            ref_lane,
+    /// Attempts to deserialize a result produced by `to_json`.
+    pub fn from_json(json: &str) -> Result<Argon2Result> {
+        let mut result: Argon2Result = serde_json::from_str(json)?;
+        let encoding = result.encoding;
+        for event in result.trace.events.iter_mut() {
+            event.decode(encoding)?;
         }
-    }
-    pub fn builder() -> Argon2ValueBuilder {
-        Argon2ValueBuilder::default()
-    }
-    pub fn to_json_value(&self) -> Value {
-        json!({
-            "first_param": self.first_param,
-            "second_param": self.second_param,
-            "hash": self.hash,
-            "ref_lane": self.ref_lane,
-            "ref_index": self.ref_index,
-        })
+        let snapshots = result
+            .snapshots
+            .iter_mut()
+            .flat_map(|s| s.blocks.iter_mut());
+        for block in result.memory.iter_mut().chain(snapshots) {
+            block.decode(encoding)?;
+        }
+        Ok(result)
     }
 }
 
-impl Argon2ValueBuilder {
-    pub fn new() -> Argon2ValueBuilder {
-        Argon2ValueBuilder {
-            first_param: String::new(),
-            second_param: String::new(),
-            hash: String::new(),
-            ref_lane: String::new(),
-            ref_index: String::new(),
-        }
-    }
-    pub fn from_argon2_value(value: Argon2Value) -> Argon2ValueBuilder {
-        Argon2ValueBuilder {
-            first_param: value.first_param,
-            second_param: value.second_param,
-            hash: value.hash,
-            ref_lane: value.ref_lane,
-            ref_index: value.ref_index,
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::hash_encoded;
+    use crate::bytes::ByteEncoding;
+    use crate::config::Config;
+    use crate::result::Argon2Result;
+    use crate::snapshot::SnapshotMode;
+    use crate::trace::{BlockCoord, BlockFill, TraceEvent, TraceLevel, TraceOptions};
+    use crate::variant::Variant;
+
+    fn result_with_block(encoding: ByteEncoding) -> Argon2Result {
+        let mut result = Argon2Result::with_encoding(encoding);
+        result.set_hash("$argon2i$v=19$m=8,t=1,p=1$c29tZXNhbHQ$AAAAAA");
+        result.record(TraceEvent::BlockFill(BlockFill {
+            pass: 0,
+            lane: 0,
+            slice: 1,
+            index: 2,
+            prev: BlockCoord { lane: 0, index: 1 },
+            reference: BlockCoord { lane: 0, index: 0 },
+            with_xor: false,
+            content: vec![0xff, 0x00, 0xc3, 0x28].into(),
+        }));
+        result
+    }
+
+    #[test]
+    fn to_json_uses_selected_encoding() {
+        let json = result_with_block(ByteEncoding::Hex).to_json();
+        assert!(json.contains(r#""encoding":"hex""#));
+        assert!(json.contains(r#""content":"ff00c328""#));
+
+        let json = result_with_block(ByteEncoding::Base64).to_json();
+        assert!(json.contains(r#""content":"/wDDKA==""#));
+
+        let json = result_with_block(ByteEncoding::Raw).to_json();
+        assert!(json.contains(r#""content":[255,0,195,40]"#));
+    }
+
+    #[test]
+    fn from_json_round_trips_every_encoding() {
+        for encoding in [ByteEncoding::Hex, ByteEncoding::Base64, ByteEncoding::Raw] {
+            let result = result_with_block(encoding);
+            let parsed = Argon2Result::from_json(&result.to_json()).unwrap();
+            assert_eq!(parsed, result);
         }
     }
-    pub fn first_param(mut self, first_param: String) -> Argon2ValueBuilder {
-        self.first_param = first_param;
-        self
-    }
-    pub fn second_param(mut self, second_param: String) -> Argon2ValueBuilder {
-        self.second_param = second_param;
-        self
-    }
-    pub fn hash(mut self, hash: String) -> Argon2ValueBuilder {
-        self.hash = hash;
-        self
-    }
-    pub fn ref_lane(mut self, ref_lane: String) -> Argon2ValueBuilder {
-        self.ref_lane = ref_lane;
-        self
-    }
-    pub fn ref_index(mut self, ref_index: String) -> Argon2ValueBuilder {
-        self.ref_index = ref_index;
-        self
-    }
 
-    pub fn build(self) -> Argon2Value {
-        Argon2Value::new(
-            self.first_param,
-            self.second_param,
-            self.hash,
-            self.ref_lane,
-            self.ref_index,
-        )
+    #[test]
+    fn from_json_accepts_reordered_keys() {
+        let result = result_with_block(ByteEncoding::Hex);
+        // Without `preserve_order` the keys are sorted, moving `type` after
+        // the fields of the event.
+        let value: serde_json::Value = serde_json::from_str(&result.to_json()).unwrap();
+        let json = serde_json::to_string(&value).unwrap();
+        assert!(json.contains(r#""slice":1,"type":"block_fill""#));
+        assert_eq!(Argon2Result::from_json(&json).unwrap(), result);
+    }
+
+    #[test]
+    fn from_json_round_trips_every_event_type() {
+        let encodings = [ByteEncoding::Hex, ByteEncoding::Base64, ByteEncoding::Raw];
+        for (encoding, stop_at_iteration) in encodings.iter().zip([None, Some(0), Some(1)]) {
+            let encoding = *encoding;
+            let config = Config {
+                variant: Variant::Argon2id,
+                mem_cost: 8,
+                time_cost: 2,
+                stop_at_iteration,
+                trace_encoding: encoding,
+                trace: TraceOptions {
+                    level: TraceLevel::QuarterRounds,
+                    ..TraceOptions::default()
+                },
+                snapshot: SnapshotMode::Full,
+                ..Config::default()
+            };
+            let mut result = Argon2Result::with_encoding(encoding);
+            hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+            assert_eq!(result.partial, stop_at_iteration.is_some());
+            let parsed = Argon2Result::from_json(&result.to_json()).unwrap();
+            assert_eq!(parsed, result);
+        }
     }
 }
@@ -0,0 +1,143 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::trace::{Trace, TraceEvent};
+
+/// Destination of the trace events of a run. Events are handed over in
+/// batches: the initialization, every filled segment (every slice when the
+/// lanes are filled in parallel) and the finalization.
+pub trait TraceSink {
+    /// Receives the next batch of events, in the order they were recorded.
+    fn write(&mut self, events: Vec<TraceEvent>);
+}
+
+/// Keeps the events in memory.
+impl TraceSink for Trace {
+    fn write(&mut self, events: Vec<TraceEvent>) {
+        self.events.extend(events);
+    }
+}
+
+/// Sink that drops every event.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct NoopSink;
+
+impl TraceSink for NoopSink {
+    fn write(&mut self, _: Vec<TraceEvent>) {}
+}
+
+/// Sink that calls a function with every batch of events.
+pub struct CallbackSink<F: FnMut(Vec<TraceEvent>)> {
+    callback: F,
+}
+
+impl<F: FnMut(Vec<TraceEvent>)> CallbackSink<F> {
+    /// Creates a new sink calling `callback` with every batch.
+    pub fn new(callback: F) -> CallbackSink<F> {
+        CallbackSink { callback }
+    }
+}
+
+impl<F: FnMut(Vec<TraceEvent>)> TraceSink for CallbackSink<F> {
+    fn write(&mut self, events: Vec<TraceEvent>) {
+        (self.callback)(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::{hash_encoded, hash_encoded_with_sink};
+    use crate::config::Config;
+    use crate::result::Argon2Result;
+    use crate::sink::{CallbackSink, NoopSink, TraceSink};
+    use crate::thread_mode::ThreadMode;
+    use crate::trace::{Trace, TraceEvent, TraceLevel, TraceOptions};
+
+    fn config(thread_mode: ThreadMode) -> Config<'static> {
+        Config {
+            lanes: 2,
+            mem_cost: 32,
+            thread_mode,
+            time_cost: 1,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        }
+    }
+
+    fn batches(config: &Config) -> (Vec<Vec<TraceEvent>>, Argon2Result) {
+        let mut batches = Vec::new();
+        let mut result = Argon2Result::new();
+        let mut sink = CallbackSink::new(|events| batches.push(events));
+        hash_encoded_with_sink(b"password", b"somesalt", config, &mut result, &mut sink).unwrap();
+        (batches, result)
+    }
+
+    #[test]
+    fn callback_sink_receives_a_batch_per_segment() {
+        let config = config(ThreadMode::Sequential);
+        let (batches, result) = batches(&config);
+        // The initialization, 4 slices of 2 lanes and the finalization.
+        assert_eq!(batches.len(), 1 + 4 * 2 + 1);
+        assert!(result.trace.events.is_empty());
+        assert!(!result.hash.is_empty());
+
+        let mut expected = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut expected).unwrap();
+        assert_eq!(batches.concat(), expected.trace.events);
+        assert_eq!(result.hash, expected.hash);
+    }
+
+    #[test]
+    fn callback_sink_receives_a_batch_per_slice_in_parallel() {
+        let config = config(ThreadMode::Parallel);
+        let (batches, _) = batches(&config);
+        assert_eq!(batches.len(), 1 + 4 + 1);
+
+        let mut expected = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut expected).unwrap();
+        assert_eq!(batches.concat(), expected.trace.events);
+    }
+
+    #[test]
+    fn callback_sink_is_not_called_without_events() {
+        let config = Config {
+            trace: TraceOptions::off(),
+            ..config(ThreadMode::Sequential)
+        };
+        let (batches, _) = batches(&config);
+        assert!(batches.is_empty());
+    }
+
+    #[test]
+    fn noop_sink_drops_events() {
+        let config = config(ThreadMode::Sequential);
+        let mut result = Argon2Result::new();
+        let encoded = hash_encoded_with_sink(
+            b"password",
+            b"somesalt",
+            &config,
+            &mut result,
+            &mut NoopSink,
+        )
+        .unwrap();
+        assert!(result.trace.events.is_empty());
+        assert_eq!(encoded, Some(result.hash));
+    }
+
+    #[test]
+    fn trace_sink_appends_events() {
+        let config = config(ThreadMode::Sequential);
+        let (batches, _) = batches(&config);
+        let mut trace = Trace::new();
+        for batch in batches.iter() {
+            trace.write(batch.clone());
+        }
+        assert_eq!(trace.events, batches.concat());
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::bytes::Bytes;
+use crate::common;
+use crate::context::Context;
+use crate::error::Error;
+use crate::memory::Memory;
+use crate::result::{Argon2Result, JsResult};
+use crate::trace::BlockCoord;
+use blake2b_simd::Params;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+/// The length of the per-block digests of `SnapshotMode::Digest`.
+pub const DIGEST_LENGTH: usize = 32;
+
+/// What is stored of the memory matrix at every sync point.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotMode {
+    /// No snapshots.
+    #[default]
+    Off,
+
+    /// The content of every block.
+    Full,
+
+    /// The BLAKE2b digest of every block.
+    Digest,
+}
+
+impl SnapshotMode {
+    /// Gets the string slice representation of the snapshot mode.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotMode::Off => "off",
+            SnapshotMode::Full => "full",
+            SnapshotMode::Digest => "digest",
+        }
+    }
+}
+
+impl FromStr for SnapshotMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(SnapshotMode::Off),
+            "full" => Ok(SnapshotMode::Full),
+            "digest" => Ok(SnapshotMode::Digest),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The memory matrix after all lanes finished a slice.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    /// The pass of the finished slice.
+    pub pass: u32,
+
+    /// The finished slice.
+    pub slice: u32,
+
+    /// Whether the slice is the last one of the pass.
+    pub end_of_pass: bool,
+
+    /// Whether `blocks` holds contents or digests.
+    pub mode: SnapshotMode,
+
+    /// The number of blocks in a lane.
+    pub lane_length: u32,
+
+    /// The content or digest of every block, lane by lane.
+    pub blocks: Vec<Bytes>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the memory after `slice` of `pass`.
+    pub fn new(
+        context: &Context,
+        memory: &Memory,
+        mode: SnapshotMode,
+        pass: u32,
+        slice: u32,
+    ) -> Snapshot {
+        let blocks = memory
+            .blocks()
+            .iter()
+            .map(|block| match mode {
+                SnapshotMode::Digest => Params::new()
+                    .hash_length(DIGEST_LENGTH)
+                    .hash(block.as_u8())
+                    .as_bytes()
+                    .into(),
+                _ => block.as_u8().into(),
+            })
+            .collect();
+        Snapshot {
+            pass,
+            slice,
+            end_of_pass: slice == common::SYNC_POINTS - 1,
+            mode,
+            lane_length: context.lane_length,
+            blocks,
+        }
+    }
+
+    /// Gets the coordinates of the blocks that differ from `previous`.
+    pub fn diff(&self, previous: &Snapshot) -> Vec<BlockCoord> {
+        self.blocks
+            .iter()
+            .zip(previous.blocks.iter())
+            .enumerate()
+            .filter(|(_, (block, old))| block != old)
+            .map(|(i, _)| BlockCoord {
+                lane: i as u32 / self.lane_length,
+                index: i as u32 % self.lane_length,
+            })
+            .collect()
+    }
+}
+
+/// Records a snapshot after `slice` of `pass` when enabled in the context.
+pub fn record(context: &Context, memory: &Memory, state: &mut Argon2Result, pass: u32, slice: u32) {
+    let mode = context.config.snapshot;
+    if mode != SnapshotMode::Off {
+        let snapshot = Snapshot::new(context, memory, mode, pass, slice);
+        state.snapshots.push(snapshot);
+    }
+}
+
+/// Gets the blocks that changed between snapshot `index - 1` and `index` of
+/// a result returned by `hash_encoded_js`, as a JSON array of coordinates.
+#[wasm_bindgen]
+pub fn snapshot_diff_js(result_json: String, index: usize) -> JsResult<String> {
+    let result = Argon2Result::from_json(&result_json)?;
+    if index == 0 || index >= result.snapshots.len() {
+        return Err(Error::IncorrectArgument.into());
+    }
+    let diff = result.snapshots[index].diff(&result.snapshots[index - 1]);
+    Ok(serde_json::to_string(&diff).map_err(Error::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::hash_encoded;
+    use crate::config::Config;
+    use crate::result::Argon2Result;
+    use crate::snapshot::{snapshot_diff_js, SnapshotMode, DIGEST_LENGTH};
+    use crate::trace::{BlockCoord, TraceEvent, TraceLevel, TraceOptions};
+
+    fn snapshots(mode: SnapshotMode, lanes: u32) -> Argon2Result {
+        let config = Config {
+            lanes,
+            mem_cost: 16 * lanes,
+            time_cost: 2,
+            snapshot: mode,
+            trace: TraceOptions::new(TraceLevel::Blocks),
+            ..Config::default()
+        };
+        let mut result = Argon2Result::new();
+        hash_encoded(b"password", b"somesalt", &config, &mut result).unwrap();
+        result
+    }
+
+    #[test]
+    fn off_records_no_snapshots() {
+        let result = snapshots(SnapshotMode::Off, 1);
+        assert!(result.snapshots.is_empty());
+    }
+
+    #[test]
+    fn full_records_every_sync_point() {
+        let result = snapshots(SnapshotMode::Full, 2);
+        assert_eq!(result.snapshots.len(), 2 * 4);
+        let last = result.snapshots.last().unwrap();
+        assert_eq!((last.pass, last.slice, last.end_of_pass), (1, 3, true));
+        assert!(!result.snapshots[2].end_of_pass);
+        assert_eq!(last.blocks.len(), 32);
+
+        let fill = result
+            .trace
+            .events
+            .iter()
+            .rev()
+            .find_map(|e| match e {
+                TraceEvent::BlockFill(fill) if fill.lane == 1 && fill.index == 15 => Some(fill),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(last.blocks[16 + 15], fill.content);
+    }
+
+    #[test]
+    fn diff_returns_blocks_of_the_finished_slice() {
+        for mode in [SnapshotMode::Full, SnapshotMode::Digest] {
+            let result = snapshots(mode, 2);
+            let diff = result.snapshots[5].diff(&result.snapshots[4]);
+            let expected: Vec<BlockCoord> = (0..2)
+                .flat_map(|lane| (4..8).map(move |index| BlockCoord { lane, index }))
+                .collect();
+            assert_eq!(diff, expected);
+        }
+    }
+
+    #[test]
+    fn digest_stores_short_digests() {
+        let result = snapshots(SnapshotMode::Digest, 1);
+        assert!(result.snapshots[0]
+            .blocks
+            .iter()
+            .all(|b| b.len() == DIGEST_LENGTH));
+    }
+
+    #[test]
+    fn snapshot_diff_js_returns_json_coordinates() {
+        let result = snapshots(SnapshotMode::Digest, 1);
+        let diff = snapshot_diff_js(result.to_json(), 1).unwrap();
+        assert_eq!(
+            diff,
+            r#"[{"lane":0,"index":4},{"lane":0,"index":5},{"lane":0,"index":6},{"lane":0,"index":7}]"#
+        );
+    }
+}
@@ -0,0 +1,722 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::common;
+use crate::config::Config;
+use crate::context::Context;
+use crate::core::{self, Position};
+use crate::encoding;
+use crate::error::Error;
+use crate::memory::Memory;
+use crate::progress::{CancellationToken, Progress};
+use crate::result::{Argon2Result, JsResult, Result};
+use crate::snapshot;
+use js_sys::{Date, Function, Promise, Reflect};
+use std::panic;
+
+use wasm_bindgen::prelude::*;
+
+/// How long a chunk of `hash_encoded_async_js` fills segments by default
+/// before yielding to the event loop, in milliseconds.
+const CHUNK_MILLIS: f64 = 16.0;
+
+/// How far a step fills the memory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Step {
+    Block,
+    Segment,
+    Slice,
+    Pass,
+    All,
+}
+
+/// Runs Argon2 in steps, returning the trace events produced by each step as
+/// a JSON array.
+///
+/// Blocks are filled in the sequential order: pass by pass, slice by slice
+/// and lane by lane. The first blocks of every lane are initialized when the
+/// stepper is created and their events are returned by the first step.
+#[wasm_bindgen]
+pub struct Argon2Stepper {
+    /// The inputs the context of every step is created from.
+    inputs: Inputs,
+
+    /// The memory matrix.
+    memory: Memory,
+
+    /// The position of the next block to fill, `None` when all passes are
+    /// filled.
+    position: Option<Position>,
+
+    /// The result holding the events not yet returned and the final hash.
+    state: Argon2Result,
+
+    /// Whether `finish` has been called.
+    finished: bool,
+}
+
+#[wasm_bindgen]
+impl Argon2Stepper {
+    /// Creates a new stepper and initializes the first blocks of every lane.
+    /// The optional `secret` and `ad` are the secret value and the associated
+    /// data.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        pwd: String,
+        salt: String,
+        config_json: String,
+        secret: Option<Vec<u8>>,
+        ad: Option<Vec<u8>>,
+    ) -> JsResult<Argon2Stepper> {
+        Ok(Argon2Stepper::from_bytes(
+            pwd.into_bytes(),
+            salt.into_bytes(),
+            &config_json,
+            secret.unwrap_or_default(),
+            ad.unwrap_or_default(),
+        )?)
+    }
+
+    /// Fills the next block.
+    pub fn step_block(&mut self) -> String {
+        self.step(Step::Block)
+    }
+
+    /// Fills the rest of the current segment.
+    pub fn step_segment(&mut self) -> String {
+        self.step(Step::Segment)
+    }
+
+    /// Fills the rest of the current slice in all lanes.
+    pub fn step_slice(&mut self) -> String {
+        self.step(Step::Slice)
+    }
+
+    /// Fills the rest of the current pass.
+    pub fn step_pass(&mut self) -> String {
+        self.step(Step::Pass)
+    }
+
+    /// Fills the remaining blocks and computes the final hash, unless the
+    /// configuration stops at an earlier pass.
+    pub fn finish(&mut self) -> String {
+        self.complete();
+        self.state.take_events_json()
+    }
+
+    /// Returns whether all blocks have been filled.
+    pub fn is_done(&self) -> bool {
+        self.position.is_none()
+    }
+
+    /// Returns the encoded hash, which is empty until `finish` is called.
+    pub fn hash(&self) -> String {
+        self.state.hash.clone()
+    }
+}
+
+impl Argon2Stepper {
+    /// Creates a new stepper like `new`, taking the inputs as bytes.
+    pub fn from_bytes(
+        pwd: Vec<u8>,
+        salt: Vec<u8>,
+        config_json: &str,
+        secret: Vec<u8>,
+        ad: Vec<u8>,
+    ) -> Result<Argon2Stepper> {
+        let config: Config<'static> = Config::from_json(config_json)?;
+        let (memory, state, position, context) = {
+            let context = Context::new(
+                Config {
+                    secret: &secret,
+                    ad: &ad,
+                    ..config.clone()
+                },
+                &pwd,
+                &salt,
+            )?;
+            let mut memory = Memory::new(context.config.lanes, context.lane_length);
+            let mut state = Argon2Result::with_encoding(context.config.trace_encoding);
+            core::initialize(&context, &mut memory, &mut state);
+            let first = Position {
+                pass: 0,
+                lane: 0,
+                slice: 0,
+                index: 0,
+            };
+            let position = normalize(&context, first);
+            record_snapshots(&context, &memory, &mut state, (0, 0), position.as_ref());
+            let context = Context {
+                config,
+                lane_length: context.lane_length,
+                memory_blocks: context.memory_blocks,
+                pwd: &[],
+                salt: &[],
+                segment_length: context.segment_length,
+            };
+            (memory, state, position, context)
+        };
+        Ok(Argon2Stepper {
+            inputs: Inputs {
+                pwd,
+                salt,
+                secret,
+                ad,
+                context,
+            },
+            memory,
+            position,
+            state,
+            finished: false,
+        })
+    }
+
+    /// Fills segments until all blocks are filled or `more` returns false,
+    /// filling at least one segment. Returns whether all blocks are filled.
+    /// The events are kept until `into_result` is called.
+    pub fn fill_segments(&mut self, mut more: impl FnMut() -> bool) -> bool {
+        loop {
+            self.advance(Step::Segment);
+            if self.is_done() || !more() {
+                return self.is_done();
+            }
+        }
+    }
+
+    /// Finishes the run and returns the result holding the hash and the
+    /// events not yet returned by a step.
+    pub fn into_result(mut self) -> Argon2Result {
+        self.complete();
+        self.state
+    }
+
+    /// Returns the progress after the segments filled so far.
+    pub fn progress(&self) -> Progress {
+        let context = self.inputs.context();
+        let (pass, slice, lane) = match &self.position {
+            Some(p) => (p.pass, p.slice, p.lane),
+            None => (core::passes(&context), 0, 0),
+        };
+        let slices = pass as u64 * common::SYNC_POINTS as u64 + slice as u64;
+        let segments = slices * context.config.lanes as u64 + lane as u64;
+        Progress::after_segments(&context, segments)
+    }
+
+    fn step(&mut self, step: Step) -> String {
+        self.advance(step);
+        self.state.take_events_json()
+    }
+
+    fn complete(&mut self) {
+        self.advance(Step::All);
+        if !self.finished {
+            self.finished = true;
+            let context = self.inputs.context();
+            if context.config.stop_at_iteration.is_some() {
+                self.state.set_partial(&self.memory);
+            } else {
+                let hash = core::finalize(&context, &self.memory, &mut self.state);
+                self.state
+                    .set_hash(&encoding::encode_string(&context, &hash));
+            }
+        }
+    }
+
+    fn advance(&mut self, step: Step) {
+        let context = self.inputs.context();
+        while let Some(position) = self.position.take() {
+            let end = if step == Step::Block {
+                position.index + 1
+            } else {
+                context.segment_length
+            };
+            core::fill_segment(&context, &position, end, &mut self.memory, &mut self.state);
+
+            let next = normalize(
+                &context,
+                Position {
+                    index: end,
+                    ..position.clone()
+                },
+            );
+            record_snapshots(
+                &context,
+                &self.memory,
+                &mut self.state,
+                (position.pass, position.slice),
+                next.as_ref(),
+            );
+            let stop = match (step, &next) {
+                (_, None) | (Step::Block, _) | (Step::Segment, _) => true,
+                (Step::Slice, Some(next)) => {
+                    next.slice != position.slice || next.pass != position.pass
+                }
+                (Step::Pass, Some(next)) => next.pass != position.pass,
+                (Step::All, Some(_)) => false,
+            };
+            self.position = next;
+            if stop {
+                break;
+            }
+        }
+    }
+}
+
+/// Hashes the password like `hash_encoded_js` without blocking the event
+/// loop. Segments are filled in chunks of about `chunk_ms` milliseconds (16
+/// by default) and the next chunk is scheduled with `setTimeout`. After
+/// every chunk `on_progress` is called with the number of blocks filled and
+/// the total number of blocks. The returned promise resolves to the same
+/// JSON result as `hash_encoded_js` and is rejected with `Error::Cancelled`
+/// when `token` is cancelled, which is checked between segments, or with
+/// the exception of `on_progress`. Incorrect inputs are thrown before any
+/// chunk is scheduled. The password and salt are byte arrays and the
+/// optional `secret` and `ad` are taken like in `hash_encoded_bytes_js`.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn hash_encoded_async_js(
+    pwd: &[u8],
+    salt: &[u8],
+    config_json: String,
+    secret: Option<Vec<u8>>,
+    ad: Option<Vec<u8>>,
+    chunk_ms: Option<f64>,
+    on_progress: Option<Function>,
+    token: Option<CancellationToken>,
+) -> JsResult<Promise> {
+    panic::set_hook(Box::new(console_error_panic_hook::hook));
+    let stepper = Argon2Stepper::from_bytes(
+        pwd.to_vec(),
+        salt.to_vec(),
+        &config_json,
+        secret.unwrap_or_default(),
+        ad.unwrap_or_default(),
+    )?;
+    let mut chunks = Some(Chunks {
+        stepper,
+        chunk_ms: chunk_ms.unwrap_or(CHUNK_MILLIS),
+        on_progress,
+        token,
+    });
+    let mut scheduled = Ok(());
+    let promise = Promise::new(&mut |resolve, reject| {
+        if let Some(chunks) = chunks.take() {
+            scheduled = chunks.schedule(resolve, reject);
+        }
+    });
+    scheduled.map(|_| promise)
+}
+
+/// The state of `hash_encoded_async_js` carried from chunk to chunk.
+struct Chunks {
+    /// The stepper filling the memory.
+    stepper: Argon2Stepper,
+
+    /// How long a chunk fills segments, in milliseconds.
+    chunk_ms: f64,
+
+    /// The function called with the progress after every chunk.
+    on_progress: Option<Function>,
+
+    /// The token checked between segments.
+    token: Option<CancellationToken>,
+}
+
+impl Chunks {
+    /// Schedules the next chunk. A chunk that fails rejects the promise, and
+    /// throws when even that fails.
+    fn schedule(self, resolve: Function, reject: Function) -> JsResult<()> {
+        let chunk = Closure::once_into_js(move || -> JsResult<()> {
+            let settled = self.run(resolve, reject.clone());
+            settled.or_else(|e| reject.call1(&JsValue::NULL, &e).map(|_| ()))
+        });
+        let set_timeout: Function =
+            Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))?.dyn_into()?;
+        set_timeout.call2(&JsValue::NULL, &chunk, &JsValue::from_f64(0.0))?;
+        Ok(())
+    }
+
+    /// Fills the segments of one chunk, then resolves the promise when all
+    /// blocks are filled or schedules the next chunk.
+    fn run(mut self, resolve: Function, reject: Function) -> JsResult<()> {
+        let cancelled = |token: &Option<CancellationToken>| {
+            token.as_ref().is_some_and(CancellationToken::is_cancelled)
+        };
+        if cancelled(&self.token) {
+            return Err(Error::Cancelled.into());
+        }
+        let deadline = Date::now() + self.chunk_ms;
+        let token = &self.token;
+        let done = self
+            .stepper
+            .fill_segments(|| Date::now() < deadline && !cancelled(token));
+        if let Some(on_progress) = &self.on_progress {
+            let progress = self.stepper.progress();
+            on_progress.call2(
+                &JsValue::NULL,
+                &JsValue::from_f64(progress.blocks_done as f64),
+                &JsValue::from_f64(progress.blocks_total as f64),
+            )?;
+        }
+        if done {
+            let json = self.stepper.into_result().to_json();
+            resolve.call1(&JsValue::NULL, &JsValue::from_str(&json))?;
+            Ok(())
+        } else {
+            self.schedule(resolve, reject)
+        }
+    }
+}
+
+/// The inputs of a stepper, owned so that the context does not have to be
+/// parsed and checked again for every step.
+struct Inputs {
+    /// The password.
+    pwd: Vec<u8>,
+
+    /// The salt.
+    salt: Vec<u8>,
+
+    /// The secret value.
+    secret: Vec<u8>,
+
+    /// The associated data.
+    ad: Vec<u8>,
+
+    /// The context checked by `Argon2Stepper::new`, without the buffers
+    /// above, which are attached by `context`.
+    context: Context<'static>,
+}
+
+impl Inputs {
+    /// Returns the context with the buffers attached.
+    fn context(&self) -> Context<'_> {
+        Context {
+            config: Config {
+                secret: &self.secret,
+                ad: &self.ad,
+                ..self.context.config.clone()
+            },
+            pwd: &self.pwd,
+            salt: &self.salt,
+            ..self.context
+        }
+    }
+}
+
+/// Records a snapshot for every slice from `from` up to the slice of `next`,
+/// which are the slices finished when moving to `next`.
+fn record_snapshots(
+    context: &Context,
+    memory: &Memory,
+    state: &mut Argon2Result,
+    from: (u32, u32),
+    next: Option<&Position>,
+) {
+    let end = next.map_or((core::passes(context), 0), |next| (next.pass, next.slice));
+    let (mut pass, mut slice) = from;
+    while (pass, slice) < end {
+        snapshot::record(context, memory, state, pass, slice);
+        slice += 1;
+        if slice == common::SYNC_POINTS {
+            slice = 0;
+            pass += 1;
+        }
+    }
+}
+
+/// Moves the position to the next block that has to be filled, skipping
+/// finished segments and the blocks filled by `core::initialize`.
+fn normalize(context: &Context, position: Position) -> Option<Position> {
+    let mut position = position;
+    loop {
+        if position.pass == 0 && position.slice == 0 && position.index < 2 {
+            position.index = 2;
+        }
+        if position.index < context.segment_length {
+            return Some(position);
+        }
+        position.index = 0;
+        position.lane += 1;
+        if position.lane == context.config.lanes {
+            position.lane = 0;
+            position.slice += 1;
+        }
+        if position.slice == common::SYNC_POINTS {
+            position.slice = 0;
+            position.pass += 1;
+        }
+        if position.pass == core::passes(context) {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::argon2::hash_encoded;
+    use crate::bytes::ByteEncoding;
+    use crate::config::Config;
+    use crate::result::Argon2Result;
+    use crate::stepper::Argon2Stepper;
+    use crate::trace::{Trace, TraceEvent};
+
+    const CONFIG: &str = r#"{"memory": "32", "parallelism": "2", "iterations": "2",
+        "variant": "Argon2id", "hash_length": "16", "trace_level": "blocks"}"#;
+
+    fn expected(config_json: &str) -> Argon2Result {
+        let mut result = Argon2Result::new();
+        hash_encoded(
+            b"password",
+            b"somesalt",
+            &Config::from_json(config_json).unwrap(),
+            &mut result,
+        )
+        .unwrap();
+        result
+    }
+
+    fn parse(json: &str) -> Vec<TraceEvent> {
+        let mut events: Vec<TraceEvent> = serde_json::from_str(json).unwrap();
+        for event in events.iter_mut() {
+            event.decode(ByteEncoding::Hex).unwrap();
+        }
+        events
+    }
+
+    fn run(config_json: &str, step: fn(&mut Argon2Stepper) -> String) -> (Trace, String) {
+        let mut stepper = Argon2Stepper::new(
+            "password".to_string(),
+            "somesalt".to_string(),
+            config_json.to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        let mut trace = Trace::new();
+        while !stepper.is_done() {
+            trace.events.extend(parse(&step(&mut stepper)));
+        }
+        trace.events.extend(parse(&stepper.finish()));
+        (trace, stepper.hash())
+    }
+
+    #[test]
+    fn step_block_fills_one_block() {
+        let mut stepper = Argon2Stepper::new(
+            "password".to_string(),
+            "somesalt".to_string(),
+            CONFIG.to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        let events = parse(&stepper.step_block());
+        // The prehash, four initial blocks each derived by H', one address
+        // block, then one reference selection and one fill.
+        assert_eq!(events.len(), 12);
+        assert_eq!(parse(&stepper.step_block()).len(), 2);
+    }
+
+    #[test]
+    fn every_step_size_matches_hash_encoded() {
+        let expected = expected(CONFIG);
+        let steps: [fn(&mut Argon2Stepper) -> String; 4] = [
+            Argon2Stepper::step_block,
+            Argon2Stepper::step_segment,
+            Argon2Stepper::step_slice,
+            Argon2Stepper::step_pass,
+        ];
+        for step in steps.iter() {
+            let (trace, hash) = run(CONFIG, *step);
+            assert_eq!(trace, expected.trace);
+            assert_eq!(hash, expected.hash);
+        }
+    }
+
+    #[test]
+    fn step_block_matches_hash_encoded_with_long_segments() {
+        for variant in ["Argon2d", "Argon2i", "Argon2id"] {
+            // Segments of 256 blocks span two address blocks.
+            let config = format!(
+                r#"{{"memory": "1024", "iterations": "1", "variant": "{}", "trace_level": "off"}}"#,
+                variant
+            );
+            let expected = expected(&config);
+            let (_, hash) = run(&config, Argon2Stepper::step_block);
+            assert_eq!(hash, expected.hash, "{}", variant);
+        }
+    }
+
+    #[test]
+    fn step_slice_stops_at_slice_boundary() {
+        let mut stepper = Argon2Stepper::new(
+            "password".to_string(),
+            "somesalt".to_string(),
+            CONFIG.to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        stepper.step_slice();
+        let events = parse(&stepper.step_slice());
+        // An address block per lane, then 2 lanes of 4 blocks, each with a
+        // reference selection and a fill.
+        assert_eq!(events.len(), 18);
+        assert!(events.iter().all(|e| match e {
+            TraceEvent::BlockFill(fill) => fill.slice == 1 && fill.pass == 0,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn finish_fills_the_smallest_memory() {
+        let config = r#"{"memory": "8", "iterations": "1", "variant": "Argon2i"}"#;
+        let expected = expected(config);
+        let (trace, hash) = run(config, Argon2Stepper::step_block);
+        assert_eq!(trace, expected.trace);
+        assert_eq!(hash, expected.hash);
+    }
+
+    #[test]
+    fn every_step_size_records_the_snapshots_of_hash_encoded() {
+        for config in [
+            r#"{"memory": "32", "parallelism": "2", "iterations": "2", "snapshot": "digest"}"#,
+            r#"{"memory": "8", "iterations": "1", "snapshot": "full"}"#,
+        ] {
+            let expected = expected(config).snapshots;
+            assert_eq!(expected.len(), if config.contains("32") { 8 } else { 4 });
+            for step in [Argon2Stepper::step_block, Argon2Stepper::step_slice] {
+                let mut stepper = Argon2Stepper::new(
+                    "password".to_string(),
+                    "somesalt".to_string(),
+                    config.to_string(),
+                    None,
+                    None,
+                )
+                .unwrap();
+                while !stepper.is_done() {
+                    step(&mut stepper);
+                }
+                stepper.finish();
+                assert_eq!(stepper.state.snapshots, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_segments_in_chunks_matches_hash_encoded() {
+        for config in [
+            CONFIG,
+            r#"{"memory": "32", "parallelism": "2", "iterations": "2", "snapshot": "digest"}"#,
+            r#"{"memory": "32", "iterations": "3", "stop_at_iteration": "1"}"#,
+        ] {
+            let mut stepper = Argon2Stepper::new(
+                "password".to_string(),
+                "somesalt".to_string(),
+                config.to_string(),
+                None,
+                None,
+            )
+            .unwrap();
+            let mut chunks = 1;
+            while !stepper.fill_segments({
+                let mut segments = 0;
+                move || {
+                    segments += 1;
+                    segments < 3
+                }
+            }) {
+                chunks += 1;
+            }
+            assert!(chunks > 1);
+            assert_eq!(stepper.into_result(), expected(config));
+        }
+    }
+
+    #[test]
+    fn progress_counts_the_filled_segments() {
+        let mut stepper = Argon2Stepper::new(
+            "password".to_string(),
+            "somesalt".to_string(),
+            CONFIG.to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(stepper.progress().blocks_done, 0);
+        assert_eq!(stepper.progress().blocks_total, 64);
+        stepper.fill_segments(|| false);
+        assert_eq!(stepper.progress().blocks_done, 4);
+        stepper.step_slice();
+        assert_eq!(stepper.progress().blocks_done, 8);
+        stepper.fill_segments(|| true);
+        assert_eq!(stepper.progress().blocks_done, 64);
+    }
+
+    #[test]
+    fn finish_honours_stop_at_iteration() {
+        let config = r#"{"memory": "32", "iterations": "3", "stop_at_iteration": "0"}"#;
+        let expected = expected(config);
+        let (trace, hash) = run(config, Argon2Stepper::step_pass);
+        assert!(expected.partial);
+        assert_eq!(trace, expected.trace);
+        assert_eq!(hash, "");
+    }
+
+    #[test]
+    fn finish_uses_secret_and_ad() {
+        let config_json = r#"{"memory": "32", "iterations": "1"}"#;
+        let mut stepper = Argon2Stepper::new(
+            "password".to_string(),
+            "somesalt".to_string(),
+            config_json.to_string(),
+            Some(b"secret".to_vec()),
+            Some(vec![0, 255]),
+        )
+        .unwrap();
+        while !stepper.is_done() {
+            stepper.step_segment();
+        }
+        stepper.finish();
+
+        let mut expected = Argon2Result::new();
+        let config = Config {
+            secret: b"secret",
+            ad: &[0, 255],
+            ..Config::from_json(config_json).unwrap()
+        };
+        hash_encoded(b"password", b"somesalt", &config, &mut expected).unwrap();
+        assert_eq!(stepper.hash(), expected.hash);
+        assert_ne!(stepper.hash(), self::expected(config_json).hash);
+    }
+
+    #[test]
+    fn from_bytes_hashes_binary_inputs() {
+        let config_json = r#"{"memory": "32", "iterations": "1"}"#;
+        let pwd = vec![0, 159, 146, 150, 255];
+        let salt = vec![255; 8];
+        let stepper = Argon2Stepper::from_bytes(
+            pwd.clone(),
+            salt.clone(),
+            config_json,
+            b"secret".to_vec(),
+            Vec::new(),
+        )
+        .unwrap();
+
+        let mut expected = Argon2Result::new();
+        let config = Config {
+            secret: b"secret",
+            ..Config::from_json(config_json).unwrap()
+        };
+        hash_encoded(&pwd, &salt, &config, &mut expected).unwrap();
+        assert_eq!(stepper.into_result().hash, expected.hash);
+    }
+}
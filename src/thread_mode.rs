@@ -46,6 +46,7 @@ impl ThreadMode {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for ThreadMode {
     fn default() -> ThreadMode {
         ThreadMode::Sequential
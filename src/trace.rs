@@ -0,0 +1,634 @@
+// Copyright (c) 2017 Martijn Rijkeboer <mrr@sru-systems.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::bytes::{ByteEncoding, Bytes};
+use crate::result;
+
+/// How much detail is recorded while hashing.
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceLevel {
+    /// Nothing.
+    #[default]
+    Off,
+
+    /// Only the address blocks and reference selections.
+    References,
+
+    /// Also the prehash, H' chains, initial and filled blocks and the tag.
+    Blocks,
+
+    /// Also the intermediate values of the compression function G.
+    Compression,
+
+    /// Also the inputs and outputs of every quarter round of G.
+    QuarterRounds,
+}
+
+impl TraceLevel {
+    /// Gets the string slice representation of the trace level.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TraceLevel::Off => "off",
+            TraceLevel::References => "references",
+            TraceLevel::Blocks => "blocks",
+            TraceLevel::Compression => "compression",
+            TraceLevel::QuarterRounds => "quarter_rounds",
+        }
+    }
+}
+
+impl FromStr for TraceLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(TraceLevel::Off),
+            "references" => Ok(TraceLevel::References),
+            "blocks" => Ok(TraceLevel::Blocks),
+            "compression" => Ok(TraceLevel::Compression),
+            "quarter_rounds" => Ok(TraceLevel::QuarterRounds),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which events are recorded while hashing.
+///
+/// The filters select the blocks whose events are recorded; `None` selects
+/// every pass, lane, slice or index. The prehash and the finalization are
+/// recorded whenever the level includes them.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TraceOptions {
+    /// The amount of detail recorded.
+    pub level: TraceLevel,
+
+    /// The passes to record.
+    pub pass: Option<Range<u32>>,
+
+    /// The lanes to record.
+    pub lane: Option<Range<u32>>,
+
+    /// The slices to record.
+    pub slice: Option<Range<u32>>,
+
+    /// The block indices within a lane to record.
+    pub index: Option<Range<u32>>,
+
+    /// Records only the blocks whose index is a multiple of `every`.
+    pub every: u32,
+}
+
+impl TraceOptions {
+    /// Creates options that record every block at `level`.
+    pub fn new(level: TraceLevel) -> TraceOptions {
+        TraceOptions {
+            level,
+            ..TraceOptions::default()
+        }
+    }
+
+    /// Creates options that record nothing.
+    pub fn off() -> TraceOptions {
+        TraceOptions::new(TraceLevel::Off)
+    }
+
+    /// Returns whether nothing is recorded.
+    pub fn is_off(&self) -> bool {
+        self.level == TraceLevel::Off
+    }
+
+    /// Returns whether the filters leave out some of the blocks.
+    pub fn is_filtered(&self) -> bool {
+        self.pass.is_some()
+            || self.lane.is_some()
+            || self.slice.is_some()
+            || self.index.is_some()
+            || self.every > 1
+    }
+
+    /// Returns whether the events of the run as a whole are recorded at
+    /// `level`.
+    pub fn records(&self, level: TraceLevel) -> bool {
+        level != TraceLevel::Off && self.level >= level
+    }
+
+    /// Returns whether the events of a segment are recorded at `level`.
+    pub fn records_segment(&self, level: TraceLevel, pass: u32, lane: u32, slice: u32) -> bool {
+        self.records(level)
+            && contains(&self.pass, pass)
+            && contains(&self.lane, lane)
+            && contains(&self.slice, slice)
+    }
+
+    /// Returns whether the events of a block are recorded at `level`.
+    pub fn records_block(
+        &self,
+        level: TraceLevel,
+        pass: u32,
+        lane: u32,
+        slice: u32,
+        index: u32,
+    ) -> bool {
+        self.records_segment(level, pass, lane, slice)
+            && contains(&self.index, index)
+            && index % self.every.max(1) == 0
+    }
+}
+
+impl Default for TraceOptions {
+    fn default() -> TraceOptions {
+        TraceOptions {
+            level: TraceLevel::default(),
+            pass: None,
+            lane: None,
+            slice: None,
+            index: None,
+            every: 1,
+        }
+    }
+}
+
+fn contains(range: &Option<Range<u32>>, value: u32) -> bool {
+    match range {
+        Some(range) => range.contains(&value),
+        None => true,
+    }
+}
+
+/// Coordinates of a block in the memory matrix.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BlockCoord {
+    /// The lane of the block.
+    pub lane: u32,
+
+    /// The index of the block within the lane.
+    pub index: u32,
+}
+
+/// One encoded input field of the H0 prehash.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct PrehashField {
+    /// The name of the field, such as `lanes`, `pwd_length` or `pwd`.
+    pub name: String,
+
+    /// The numeric value of the length and parameter fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u32>,
+
+    /// The bytes hashed for the field, little-endian for numbers.
+    pub bytes: Bytes,
+}
+
+/// Event recorded when the H0 prehash is computed from the inputs.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Prehash {
+    /// The fields in the order they are hashed.
+    pub fields: Vec<PrehashField>,
+
+    /// The 64-byte BLAKE2b digest of the concatenated fields.
+    pub digest: Bytes,
+}
+
+/// The bytes of one V_i of H' copied to the output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct HprimePart {
+    /// The index of V_i in `Hprime::v`, starting at 0 for V_1.
+    pub v: u32,
+
+    /// The offset of the part within the output.
+    pub offset: u32,
+
+    /// The number of bytes copied from the start of V_i.
+    pub length: u32,
+}
+
+/// Event recorded when the variable-length hash function H' is computed.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Hprime {
+    /// The requested output length T.
+    pub length: u32,
+
+    /// The input X.
+    pub input: Bytes,
+
+    /// The BLAKE2b outputs V_1, V_2, ... in order. There is a single one
+    /// when T is at most 64.
+    pub v: Vec<Bytes>,
+
+    /// The parts of the V_i that make up the output, in output order.
+    pub parts: Vec<HprimePart>,
+
+    /// The output.
+    pub output: Bytes,
+}
+
+/// Event recorded when one of the first two blocks of a lane is initialized
+/// from H0.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BlockInit {
+    /// The lane of the block.
+    pub lane: u32,
+
+    /// The index of the block within the lane (0 or 1).
+    pub index: u32,
+
+    /// The H0 prehash digest.
+    pub h0: Bytes,
+
+    /// The little-endian block index and lane appended to H0.
+    pub seed: Bytes,
+
+    /// The resulting block content.
+    pub content: Bytes,
+}
+
+/// Event recorded when a block of pseudo-random addresses is generated for
+/// data-independent addressing (Argon2i and the first half of the first pass
+/// of Argon2id).
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct AddressBlock {
+    /// The pass field of the input block.
+    pub pass: u32,
+
+    /// The lane field of the input block.
+    pub lane: u32,
+
+    /// The slice field of the input block.
+    pub slice: u32,
+
+    /// The total number of memory blocks field of the input block.
+    pub memory_blocks: u32,
+
+    /// The total number of passes field of the input block.
+    pub time_cost: u32,
+
+    /// The numeric variant field of the input block.
+    pub variant: u32,
+
+    /// The counter field of the input block, starting at 1.
+    pub counter: u32,
+
+    /// The generated [J1, J2] pairs, the low and high 32 bits of every
+    /// address.
+    pub addresses: Vec<[u32; 2]>,
+}
+
+/// Event recorded when the reference block for a new block is selected.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ReferenceSelection {
+    /// The pass of the block being filled.
+    pub pass: u32,
+
+    /// The lane of the block being filled.
+    pub lane: u32,
+
+    /// The slice of the block being filled.
+    pub slice: u32,
+
+    /// The index of the block being filled within the lane.
+    pub index: u32,
+
+    /// J1, the low 32 bits of the pseudo-random value.
+    pub j1: u32,
+
+    /// J2, the high 32 bits of the pseudo-random value.
+    pub j2: u32,
+
+    /// Whether the reference block is in the lane of the block being filled.
+    pub same_lane: bool,
+
+    /// The number of blocks the reference block can be chosen from.
+    pub reference_area_size: u32,
+
+    /// The position of the reference block within the reference area,
+    /// counted from its start.
+    pub relative_position: u32,
+
+    /// The index within the reference lane where the reference area starts.
+    pub start_position: u32,
+
+    /// The lane of the reference block.
+    pub ref_lane: u32,
+
+    /// The index of the reference block within its lane.
+    pub ref_index: u32,
+}
+
+/// Event recorded when a block is filled by the compression function.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BlockFill {
+    /// The pass of the block.
+    pub pass: u32,
+
+    /// The lane of the block.
+    pub lane: u32,
+
+    /// The slice of the block.
+    pub slice: u32,
+
+    /// The index of the block within the lane.
+    pub index: u32,
+
+    /// The coordinates of the previous block.
+    pub prev: BlockCoord,
+
+    /// The coordinates of the reference block.
+    pub reference: BlockCoord,
+
+    /// Whether the old block content was XORed into the result (version
+    /// 0x13 and pass > 0).
+    pub with_xor: bool,
+
+    /// The resulting block content.
+    pub content: Bytes,
+}
+
+/// One application of the quarter round function GB inside the permutation P.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct QuarterRound {
+    /// The indices of the words a, b, c and d within the block.
+    pub words: [u32; 4],
+
+    /// The words a, b, c and d before the round, little-endian.
+    pub input: Bytes,
+
+    /// The words a, b, c and d after the round, little-endian.
+    pub output: Bytes,
+}
+
+/// Event recorded with the intermediate values of the compression function G
+/// when a block is filled.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Compression {
+    /// The pass of the block.
+    pub pass: u32,
+
+    /// The lane of the block.
+    pub lane: u32,
+
+    /// The slice of the block.
+    pub slice: u32,
+
+    /// The index of the block within the lane.
+    pub index: u32,
+
+    /// R = X xor Y, the previous block XOR the reference block.
+    pub r: Bytes,
+
+    /// Q, the state after applying P to the columns of R.
+    pub q: Bytes,
+
+    /// Z, the state after applying P to the rows of Q.
+    pub z: Bytes,
+
+    /// The output Z xor R, also XORed with the old block when required.
+    pub output: Bytes,
+
+    /// The quarter rounds in order: 64 for the columns, then 64 for the
+    /// rows. Only recorded at `TraceLevel::QuarterRounds`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rounds: Option<Vec<QuarterRound>>,
+}
+
+/// The last block of a lane XORed into the input of the final H'.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct LastBlock {
+    /// The coordinates of the block.
+    pub block: BlockCoord,
+
+    /// The content of the block.
+    pub content: Bytes,
+
+    /// The XOR of the last blocks of this lane and all lanes before it.
+    pub accumulator: Bytes,
+}
+
+/// Event recorded when the final tag is computed.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Finalization {
+    /// The last block of every lane in lane order. The accumulator of the
+    /// last lane is the input of H'.
+    pub blocks: Vec<LastBlock>,
+
+    /// The raw tag bytes.
+    pub tag: Bytes,
+}
+
+/// A single step of an Argon2 run.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// The H0 prehash was computed.
+    Prehash(Prehash),
+
+    /// The variable-length hash function H' was computed.
+    Hprime(Hprime),
+
+    /// One of the first two blocks of a lane was initialized.
+    BlockInit(BlockInit),
+
+    /// A block of addresses was generated.
+    AddressBlock(AddressBlock),
+
+    /// A reference block was selected.
+    ReferenceSelection(ReferenceSelection),
+
+    /// The compression function G was applied.
+    Compression(Compression),
+
+    /// A block was filled.
+    BlockFill(BlockFill),
+
+    /// The final tag was computed.
+    Finalization(Finalization),
+}
+
+impl TraceEvent {
+    /// Decodes the byte payloads of an event read from JSON written in
+    /// `encoding`.
+    pub fn decode(&mut self, encoding: ByteEncoding) -> result::Result<()> {
+        for payload in self.payloads_mut() {
+            payload.decode(encoding)?;
+        }
+        Ok(())
+    }
+
+    /// Gets every byte payload of the event.
+    fn payloads_mut(&mut self) -> Vec<&mut Bytes> {
+        match self {
+            TraceEvent::Prehash(prehash) => {
+                let mut payloads: Vec<_> =
+                    prehash.fields.iter_mut().map(|f| &mut f.bytes).collect();
+                payloads.push(&mut prehash.digest);
+                payloads
+            }
+            TraceEvent::Hprime(hprime) => {
+                let mut payloads = vec![&mut hprime.input, &mut hprime.output];
+                payloads.extend(hprime.v.iter_mut());
+                payloads
+            }
+            TraceEvent::BlockInit(init) => vec![&mut init.h0, &mut init.seed, &mut init.content],
+            TraceEvent::AddressBlock(_) | TraceEvent::ReferenceSelection(_) => Vec::new(),
+            TraceEvent::Compression(compression) => {
+                let mut payloads = vec![
+                    &mut compression.r,
+                    &mut compression.q,
+                    &mut compression.z,
+                    &mut compression.output,
+                ];
+                for round in compression.rounds.iter_mut().flatten() {
+                    payloads.push(&mut round.input);
+                    payloads.push(&mut round.output);
+                }
+                payloads
+            }
+            TraceEvent::BlockFill(fill) => vec![&mut fill.content],
+            TraceEvent::Finalization(finalization) => {
+                let mut payloads = vec![&mut finalization.tag];
+                for block in finalization.blocks.iter_mut() {
+                    payloads.push(&mut block.content);
+                    payloads.push(&mut block.accumulator);
+                }
+                payloads
+            }
+        }
+    }
+}
+
+/// Ordered list of the events recorded during an Argon2 run.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Trace {
+    /// The recorded events in the order they occurred.
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// Creates a new empty trace.
+    pub fn new() -> Trace {
+        Trace { events: Vec::new() }
+    }
+
+    /// Appends an event to the trace.
+    pub fn push(&mut self, event: TraceEvent) {
+        self.events.push(event);
+    }
+
+    /// Moves all events of `other` to the end of the trace.
+    pub fn append(&mut self, other: &mut Trace) {
+        self.events.append(&mut other.events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::trace::{
+        Finalization, ReferenceSelection, Trace, TraceEvent, TraceLevel, TraceOptions,
+    };
+
+    #[test]
+    fn new_returns_empty_trace() {
+        let trace = Trace::new();
+        assert!(trace.events.is_empty());
+    }
+
+    #[test]
+    fn push_appends_event() {
+        let mut trace = Trace::new();
+        let event = TraceEvent::Finalization(Finalization {
+            blocks: Vec::new(),
+            tag: vec![1, 2].into(),
+        });
+        trace.push(event.clone());
+        assert_eq!(trace.events, vec![event]);
+    }
+
+    #[test]
+    fn append_moves_events() {
+        let event = TraceEvent::Finalization(Finalization {
+            blocks: Vec::new(),
+            tag: vec![3].into(),
+        });
+        let mut trace = Trace::new();
+        let mut other = Trace::new();
+        other.push(event.clone());
+        trace.append(&mut other);
+        assert_eq!(trace.events, vec![event]);
+        assert!(other.events.is_empty());
+    }
+
+    #[test]
+    fn default_options_record_nothing() {
+        assert_eq!(TraceOptions::default(), TraceOptions::off());
+        assert!(!TraceOptions::default().records(TraceLevel::References));
+
+        let options = TraceOptions::new(TraceLevel::Blocks);
+        assert!(options.records_block(TraceLevel::Blocks, 2, 3, 1, 17));
+        assert!(!options.records_block(TraceLevel::Compression, 0, 0, 0, 2));
+        assert!(!options.is_filtered());
+        let every_other = TraceOptions {
+            every: 2,
+            ..TraceOptions::default()
+        };
+        assert!(every_other.is_filtered());
+    }
+
+    #[test]
+    fn filters_select_blocks() {
+        let options = TraceOptions {
+            level: TraceLevel::Blocks,
+            lane: Some(1..2),
+            slice: Some(2..3),
+            index: Some(8..16),
+            every: 4,
+            ..TraceOptions::default()
+        };
+        assert!(options.records_block(TraceLevel::Blocks, 0, 1, 2, 8));
+        assert!(options.records_block(TraceLevel::References, 5, 1, 2, 12));
+        assert!(!options.records_block(TraceLevel::Blocks, 0, 0, 2, 8));
+        assert!(!options.records_block(TraceLevel::Blocks, 0, 1, 3, 8));
+        assert!(!options.records_block(TraceLevel::Blocks, 0, 1, 2, 16));
+        assert!(!options.records_block(TraceLevel::Blocks, 0, 1, 2, 9));
+        assert!(options.records_segment(TraceLevel::Blocks, 0, 1, 2));
+        assert!(options.records(TraceLevel::Blocks));
+    }
+
+    #[test]
+    fn event_serializes_with_type_tag_and_numeric_fields() {
+        let event = TraceEvent::ReferenceSelection(ReferenceSelection {
+            pass: 1,
+            lane: 2,
+            slice: 3,
+            index: 4,
+            j1: 5,
+            j2: 6,
+            same_lane: false,
+            reference_area_size: 8,
+            relative_position: 2,
+            start_position: 5,
+            ref_lane: 0,
+            ref_index: 7,
+        });
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"type":"reference_selection","pass":1,"lane":2,"slice":3,"index":4,"#,
+                r#""j1":5,"j2":6,"same_lane":false,"reference_area_size":8,"#,
+                r#""relative_position":2,"start_position":5,"ref_lane":0,"ref_index":7}"#
+            )
+        );
+        let parsed: TraceEvent = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, event);
+    }
+}
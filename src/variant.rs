@@ -58,6 +58,7 @@ impl Variant {
     }
 
     /// Attempts to create a variant from a string slice.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(str: &str) -> Result<Variant> {
         match str {
             "Argon2d" => Ok(Variant::Argon2d),
@@ -81,6 +82,7 @@ impl Variant {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Variant {
     fn default() -> Variant {
         Variant::Argon2i
@@ -29,6 +29,7 @@ impl Version {
     }
 
     /// Attempts to create a version from a string slice.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(str: &str) -> Result<Version> {
         match str {
             "16" => Ok(Version::Version10),
@@ -54,6 +55,7 @@ impl Version {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Version {
     fn default() -> Version {
         Version::Version13